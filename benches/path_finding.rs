@@ -0,0 +1,267 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pathfinding::prelude::{astar, bfs_reach};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use sim_something::components::path_finding::grid::GridPosition;
+use sim_something::resources::abstract_graph::{AbstractGraph, CLUSTER_SIZE};
+use sim_something::resources::nav_grid::{CornerCutting, NavGrid};
+use sim_something::systems::path_finding::{DIAGONAL_COST, STRAIGHT_COST};
+
+const FLOOR: &str = "assets/floor1.txt";
+const REQUESTS: usize = 32;
+const THREADS: usize = 4;
+const OFFSETS: [(i64, i64); 8] = [
+    (0, 1),
+    (0, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (1, 1),
+    (-1, -1),
+    (1, -1),
+];
+
+type Moves = Vec<(GridPosition, u32)>;
+
+/// The glyphs of `floor1.txt`, one row per line.
+fn floor1() -> Vec<Vec<char>> {
+    fs::read_to_string(FLOOR)
+        .expect("benchmarks run from the crate root")
+        .lines()
+        .map(|line| line.chars().collect())
+        .collect()
+}
+
+fn walkable(glyph: char) -> bool {
+    !matches!(glyph, 'W' | 'T')
+}
+
+/// Octile distance on a single floor.
+fn heuristic(from: &GridPosition, to: &GridPosition) -> u32 {
+    let dx = from.tile.x.abs_diff(to.tile.x);
+    let dy = from.tile.y.abs_diff(to.tile.y);
+    STRAIGHT_COST * max(dx, dy) + (DIAGONAL_COST - STRAIGHT_COST) * min(dx, dy)
+}
+
+fn tiles(size: UVec2) -> Vec<GridPosition> {
+    (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| GridPosition::new(UVec2::new(x, y), 0)))
+        .collect()
+}
+
+fn nav_grid(rows: &[Vec<char>]) -> NavGrid {
+    let size = UVec2::new(rows[0].len() as u32, rows.len() as u32);
+    let mut grid = NavGrid::new(size, 1);
+    for position in tiles(size) {
+        let glyph = rows[position.tile.y as usize][position.tile.x as usize];
+        grid.set_walkable(&position, walkable(glyph));
+    }
+    grid
+}
+
+/// Pairs of walkable tiles that can reach each other, the same for every run.
+fn requests(grid: &NavGrid, min_distance: u32) -> Vec<(GridPosition, GridPosition)> {
+    let tiles = tiles(grid.size())
+        .into_iter()
+        .filter(|position| grid.is_walkable(position))
+        .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(9);
+    let start = *tiles.choose(&mut rng).unwrap();
+    let reachable = bfs_reach(start, |node| {
+        grid.neighbours(node, CornerCutting::Never)
+            .into_iter()
+            .map(|(neighbour, _)| neighbour)
+    })
+    .collect::<Vec<_>>();
+
+    let mut requests = Vec::with_capacity(REQUESTS);
+    while requests.len() < REQUESTS {
+        let from = *reachable.choose(&mut rng).unwrap();
+        let to = *reachable.choose(&mut rng).unwrap();
+        if heuristic(&from, &to) >= min_distance * STRAIGHT_COST {
+            requests.push((from, to));
+        }
+    }
+    requests
+}
+
+/// The navigation as `calculate_paths` searched it before the `NavGrid`: tiles are entities,
+/// walkability is a list of entities and moves are filled in lazily behind a lock that every
+/// search shares.
+struct LockedNavMesh {
+    size: UVec2,
+    /// The entity of every tile, by row and column.
+    storage: Vec<Option<u32>>,
+    walkable: Arc<Vec<u32>>,
+    mesh: Arc<Mutex<HashMap<GridPosition, Moves>>>,
+}
+
+impl LockedNavMesh {
+    fn new(rows: &[Vec<char>]) -> Self {
+        let size = UVec2::new(rows[0].len() as u32, rows.len() as u32);
+        let glyphs = rows.iter().flatten().collect::<Vec<_>>();
+        LockedNavMesh {
+            size,
+            storage: (0..glyphs.len() as u32).map(Some).collect(),
+            walkable: Arc::new(
+                (0..glyphs.len() as u32)
+                    .filter(|entity| walkable(*glyphs[*entity as usize]))
+                    .collect(),
+            ),
+            mesh: Arc::default(),
+        }
+    }
+
+    fn entity(&self, x: i64, y: i64) -> Option<u32> {
+        let x = u32::try_from(x).ok().filter(|x| *x < self.size.x)?;
+        let y = u32::try_from(y).ok().filter(|y| *y < self.size.y)?;
+        self.storage[(y * self.size.x + x) as usize]
+    }
+
+    fn neighbours(&self, current: &GridPosition) -> Moves {
+        let mut mesh = self.mesh.lock().unwrap();
+        if let Some(moves) = mesh.get(current) {
+            return moves.clone();
+        }
+
+        let (x, y) = (current.tile.x as i64, current.tile.y as i64);
+        let mut moves = Vec::with_capacity(8);
+        if self
+            .entity(x, y)
+            .map_or(false, |entity| self.walkable.contains(&entity))
+        {
+            for (dx, dy) in OFFSETS {
+                let walkable = self
+                    .entity(x + dx, y + dy)
+                    .map_or(false, |entity| self.walkable.contains(&entity));
+                if walkable {
+                    let cost = if dx == 0 || dy == 0 {
+                        STRAIGHT_COST
+                    } else {
+                        DIAGONAL_COST
+                    };
+                    let tile = UVec2::new((x + dx) as u32, (y + dy) as u32);
+                    moves.push((GridPosition::new(tile, current.floor), cost));
+                }
+            }
+        }
+        mesh.insert(*current, moves.clone());
+        moves
+    }
+}
+
+/// Run every request, spread over `THREADS` threads like the tasks of `calculate_paths`.
+fn search_all<S>(requests: &[(GridPosition, GridPosition)], successors: S)
+where
+    S: Fn(&GridPosition) -> Moves + Sync,
+{
+    thread::scope(|scope| {
+        for chunk in requests.chunks((requests.len() + THREADS - 1) / THREADS) {
+            let successors = &successors;
+            scope.spawn(move || {
+                for (from, to) in chunk {
+                    black_box(astar(
+                        from,
+                        |node| successors(node),
+                        |node| heuristic(node, to),
+                        |node| node == to,
+                    ));
+                }
+            });
+        }
+    });
+}
+
+/// The shared `NavGrid` against the lazily filled nav mesh it replaced.
+fn nav_grid_against_locked_mesh(c: &mut Criterion) {
+    let rows = floor1();
+    let grid = nav_grid(&rows);
+    let requests = requests(&grid, 0);
+
+    let mut group = c.benchmark_group("nav_grid");
+    group.sample_size(10);
+
+    // The old mesh is only filled in by searches, a fresh one includes the cost of filling it
+    group.bench_function("locked_mesh_cold", |b| {
+        b.iter_with_setup(
+            || LockedNavMesh::new(&rows),
+            |mesh| search_all(&requests, |node| mesh.neighbours(node)),
+        )
+    });
+
+    let mesh = LockedNavMesh::new(&rows);
+    search_all(&requests, |node| mesh.neighbours(node));
+    group.bench_function("locked_mesh_warm", |b| {
+        b.iter(|| search_all(&requests, |node| mesh.neighbours(node)))
+    });
+
+    // Before corner cutting rules, every diagonal next to a walkable tile was allowed
+    group.bench_function("nav_grid", |b| {
+        b.iter(|| {
+            search_all(&requests, |node| {
+                grid.neighbours(node, CornerCutting::Always)
+            })
+        })
+    });
+
+    group.finish();
+}
+
+/// HPA* against A* over the whole grid, between tiles far enough apart to search hierarchically.
+fn hierarchical_against_grid(c: &mut Criterion) {
+    let grid = nav_grid(&floor1());
+    let mut graph = AbstractGraph::default();
+    graph.update(&grid, &tiles(grid.size()), CornerCutting::Never);
+    let requests = requests(&grid, 2 * CLUSTER_SIZE);
+    let successors = |node: &GridPosition| grid.neighbours(node, CornerCutting::Never);
+
+    let mut group = c.benchmark_group("hierarchical");
+    group.sample_size(10);
+
+    group.bench_function("astar", |b| {
+        b.iter(|| {
+            for (from, to) in &requests {
+                black_box(astar(
+                    from,
+                    |node| successors(node),
+                    |node| heuristic(node, to),
+                    |node| node == to,
+                ));
+            }
+        })
+    });
+
+    group.bench_function("hpa", |b| {
+        b.iter(|| {
+            for (from, to) in &requests {
+                black_box(graph.find_path(
+                    &grid,
+                    from,
+                    to,
+                    CornerCutting::Never,
+                    successors,
+                    heuristic,
+                ));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    nav_grid_against_locked_mesh,
+    hierarchical_against_grid
+);
+criterion_main!(benches);
@@ -1,3 +1,4 @@
+use bevy::ecs::system::EntityCommands;
 use bevy::math::vec3;
 use bevy::prelude::*;
 
@@ -5,15 +6,22 @@ use bevy_mouse_tracking_plugin::MousePosWorld;
 use bevy_prototype_lyon::prelude::*;
 
 use crate::components::dna::Dna;
-use crate::components::path_finding::path::Destination;
+use crate::components::path_finding::grid::Floor;
+use crate::components::path_finding::path::{Chase, Destination, PathPriority};
 use crate::components::steering::boid::{Acceleration, Mass, MaxForce, MaxSpeed, Velocity};
+use crate::editor::simulating;
+use crate::map::asset::MapDefinition;
+use crate::map::{grid_to_world2d, ActiveFloor, MapSize};
+use rand::seq::SliceRandom;
 use rand::Rng;
 
 pub struct ActorPlugin;
 
 impl Plugin for ActorPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(spawn_actor);
+        app.add_system(spawn_at_spawn_points)
+            .add_system(spawn_actor.with_run_criteria(simulating))
+            .add_system(spawn_chaser.with_run_criteria(simulating));
     }
 }
 
@@ -21,28 +29,98 @@ fn spawn_actor(
     mut commands: Commands,
     mouse: Res<Input<MouseButton>>,
     mouse_pos: Res<MousePosWorld>,
+    active_floor: Res<ActiveFloor>,
+    map_size: Res<MapSize>,
 ) {
     if !mouse.pressed(MouseButton::Right) {
         return;
     }
 
     let mut rng = rand::thread_rng();
+    // The map is loaded in the background, there is nowhere to go until it is spawned
+    let destination_tile = match map_size.random_position(&mut rng) {
+        Some(position) => position,
+        None => return,
+    };
+
+    let color = Color::from([rng.gen(), rng.gen(), rng.gen()]);
+
+    // Actors wander off to a random tile, the player did not send them there
+    create_actor(&mut commands, mouse_pos.truncate(), active_floor.0, color)
+        .insert(PathPriority::Idle)
+        .insert(Destination(destination_tile));
+}
+
+/// Spawn an actor on every spawn point of a newly loaded map, each heads for a random zone.
+fn spawn_at_spawn_points(
+    mut commands: Commands,
+    mut map_events: EventReader<AssetEvent<MapDefinition>>,
+    maps: Res<Assets<MapDefinition>>,
+) {
+    let mut rng = rand::thread_rng();
+    for event in map_events.iter() {
+        let map = match event {
+            AssetEvent::Created { handle } => match maps.get(handle) {
+                Some(map) => map,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        for spawn_point in &map.spawn_points {
+            let color = Color::from([rng.gen(), rng.gen(), rng.gen()]);
+            let position = grid_to_world2d(&spawn_point.tile);
+            let mut actor = create_actor(&mut commands, position, spawn_point.floor, color);
+            if let Some(destination) = map.random_zone_tile(&mut rng) {
+                actor
+                    .insert(PathPriority::Idle)
+                    .insert(Destination(destination));
+            }
+        }
+    }
+}
 
+/// Spawn an actor that chases a random other actor.
+fn spawn_chaser(
+    mut commands: Commands,
+    mouse: Res<Input<MouseButton>>,
+    mouse_pos: Res<MousePosWorld>,
+    active_floor: Res<ActiveFloor>,
+    actor_query: Query<Entity, With<Dna>>,
+) {
+    if !mouse.just_pressed(MouseButton::Middle) {
+        return;
+    }
+
+    let actors = actor_query.iter().collect::<Vec<_>>();
+    let mut rng = rand::thread_rng();
+    let target = match actors.choose(&mut rng) {
+        Some(target) => *target,
+        None => return,
+    };
+
+    let color = Color::from([rng.gen(), rng.gen(), rng.gen()]);
+    create_actor(&mut commands, mouse_pos.truncate(), active_floor.0, color)
+        .insert(Chase::new(target));
+}
+
+/// Spawn an actor with the default steering settings, drawn as a circle in the given color.
+pub fn create_actor<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    position: Vec2,
+    floor: u32,
+    color: Color,
+) -> EntityCommands<'w, 's, 'a> {
     let circle = shapes::Circle {
         radius: 6.0,
         center: Vec2::ZERO,
     };
 
-    let x = rng.gen_range(0..255) as u32;
-    let y = rng.gen_range(0..255) as u32;
-    let destination_tile = UVec2::new(x, y);
-
-    let color = Color::from([rng.gen(), rng.gen(), rng.gen()]);
-
-    commands
-        .spawn()
+    let mut actor = commands.spawn();
+    actor
         .insert(Name::new("Actor"))
         .insert(Dna::random())
+        .insert(Floor(floor))
         .insert(Velocity(Vec2::ONE))
         .insert(Mass(2.0))
         .insert(MaxSpeed(1.0))
@@ -55,9 +133,9 @@ fn spawn_actor(
                 outline_mode: StrokeMode::new(Color::BLACK, 1.0),
             },
             Transform {
-                translation: vec3(mouse_pos.x, mouse_pos.y, 900.0),
+                translation: vec3(position.x, position.y, 900.0),
                 ..Default::default()
             },
-        ))
-        .insert(Destination(destination_tile));
+        ));
+    actor
 }
@@ -42,11 +42,14 @@ fn camera_movement(
             direction -= Vec3::new(0.0, 1.0, 0.0);
         }
 
-        if keyboard_input.pressed(KeyCode::Z) {
+        // Ctrl+Z is undo in the editor
+        let control = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+
+        if keyboard_input.pressed(KeyCode::Z) && !control {
             ortho.scale += 0.1;
         }
 
-        if keyboard_input.pressed(KeyCode::X) {
+        if keyboard_input.pressed(KeyCode::X) && !control {
             ortho.scale -= 0.1;
         }
 
@@ -3,3 +3,44 @@ use bevy_inspector_egui::Inspectable;
 
 #[derive(Component, Inspectable, Default)]
 pub struct Walkable;
+
+/// The floor of the building an entity is on.
+/// Both the tilemap layers and the actors walking on them carry this component.
+#[derive(Component, Inspectable, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Floor(pub u32);
+
+/// A single tile on a specific floor of the building.
+#[derive(Inspectable, Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct GridPosition {
+    pub tile: UVec2,
+    pub floor: u32,
+}
+
+impl GridPosition {
+    pub fn new(tile: UVec2, floor: u32) -> GridPosition {
+        GridPosition { tile, floor }
+    }
+
+    /// The tile `dx` columns and `dy` rows away on the same floor, unless that is left of or
+    /// below the map.
+    pub fn offset(&self, dx: i64, dy: i64) -> Option<GridPosition> {
+        let x = u32::try_from(self.tile.x as i64 + dx).ok()?;
+        let y = u32::try_from(self.tile.y as i64 + dy).ok()?;
+        Some(GridPosition::new(UVec2::new(x, y), self.floor))
+    }
+}
+
+/// Multiplier on the cost of moving onto a tile, from the legend.
+/// Tiles without one cost the same as plain floor.
+#[derive(Component, Inspectable, Clone, Copy, PartialEq, Debug)]
+pub struct TerrainCost(pub f32);
+
+/// Staircase tile that connects to a tile on another floor.
+#[derive(Component, Inspectable, Default)]
+pub struct Staircase {
+    pub to: GridPosition,
+}
+
+/// The legend glyph of the tile type a tile was spawned or painted as.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Glyph(pub char);
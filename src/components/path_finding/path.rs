@@ -1,11 +1,126 @@
+use std::collections::VecDeque;
+
 use bevy::{prelude::*, tasks::Task};
 use bevy_inspector_egui::Inspectable;
+use serde::{Deserialize, Serialize};
+
+use crate::events::path_finding::PathFailure;
+use crate::systems::path_finding::d_star_lite::DStarLiteSearch;
+use crate::systems::path_finding::finder::SearchStats;
+
+use super::grid::GridPosition;
 
 #[derive(Component, Inspectable)]
-pub struct Destination(pub UVec2);
+pub struct Destination(pub GridPosition);
+
+/// Keeps the `Destination` of an actor on the tile of another entity, and pursues the entity
+/// without a path once it is close.
+#[derive(Component)]
+pub struct Chase {
+    pub target: Entity,
+    /// Tile of the target when the last path was requested.
+    pub planned: Option<GridPosition>,
+    /// Seconds since startup when the last path was requested.
+    pub requested_at: f64,
+}
 
+impl Chase {
+    pub fn new(target: Entity) -> Self {
+        Chase {
+            target,
+            planned: None,
+            requested_at: f64::NEG_INFINITY,
+        }
+    }
+}
+
+/// A search running on the async compute task pool. The search kept by algorithms that repair
+/// their paths comes along with the path.
+pub type SearchTask = Task<(
+    Result<FoundPath, PathFailure>,
+    SearchStats,
+    Option<DStarLiteSearch>,
+)>;
+
+/// A running search and the request it was started for.
 #[derive(Component)]
-pub struct PendingPath(pub Task<Option<FoundPath>>);
+pub struct PendingPath {
+    /// `None` while the actor waits for a search the `PathCache` shares between identical requests.
+    pub task: Option<SearchTask>,
+    pub request: u64,
+    /// Step of the reservation clock a cooperative search planned the path to start at.
+    pub start: Option<u32>,
+}
 
 #[derive(Component, Inspectable)]
-pub struct FoundPath(pub Vec<UVec2>);
+pub struct FoundPath(pub Vec<GridPosition>);
+
+/// The destination the path an actor follows was planned for.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PlannedFor(pub GridPosition);
+
+/// The search a path was found with, kept to repair the path when tiles along it change.
+#[derive(Component)]
+pub struct IncrementalPlanner(pub DStarLiteSearch);
+
+/// Step of the reservation clock at which a cooperatively planned `FoundPath` starts.
+/// Each tile of the path takes a step, a tile that repeats is waited on.
+#[derive(Component)]
+pub struct Timetable(pub u32);
+
+/// How urgently an actor needs a path, more urgent requests are searched first.
+/// Actors without a priority are searched as `Normal`.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum PathPriority {
+    /// Wandering around without a goal.
+    Idle,
+    Normal,
+    /// Moves the player asked for.
+    Ordered,
+}
+
+impl Default for PathPriority {
+    fn default() -> Self {
+        PathPriority::Normal
+    }
+}
+
+/// The algorithm that searches the paths of an actor, instead of the one `PathFindingPlugin`
+/// is configured with. Also used as the global default.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PathAlgorithm {
+    /// A* with Jump Point Search on uniform grids and HPA* for long paths.
+    AStar,
+    /// Dijkstra's algorithm, A* without a heuristic.
+    Dijkstra,
+    /// Breadth-first search, finds the path with the fewest moves whatever they cost.
+    BreadthFirst,
+    /// Theta*, an any-angle search whose paths are not bound to the eight grid directions.
+    ThetaStar,
+    /// D* Lite, keeps its search to repair the path when tiles along it change.
+    DStarLite,
+}
+
+impl Default for PathAlgorithm {
+    fn default() -> Self {
+        PathAlgorithm::AStar
+    }
+}
+
+/// Marks an actor whose path was restored from a snapshot.
+/// Adding its destination does not start a new search.
+#[derive(Component)]
+pub struct RestoredPath;
+
+/// Part of a found path that lies on a single floor.
+pub struct PathLeg {
+    pub floor: u32,
+    pub path: Vec<Vec2>,
+    /// Step of the reservation clock before which the leg may not be started.
+    pub depart: u32,
+}
+
+/// The legs of a found path that still need to be walked, in order.
+/// A new leg starts each time the path takes a staircase.
+#[derive(Component, Default)]
+pub struct PathLegs(pub VecDeque<PathLeg>);
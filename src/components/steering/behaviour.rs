@@ -2,6 +2,8 @@ use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
 use core::panic;
 
+use crate::components::path_finding::grid::GridPosition;
+
 #[derive(Component, Inspectable)]
 pub struct Seek {
     pub target: Vec2,
@@ -10,11 +12,12 @@ pub struct Seek {
 #[derive(Component, Inspectable)]
 pub struct Flee(pub Vec2);
 
+/// Seek where a moving target will be `lookahead` frames from now.
 #[derive(Component, Inspectable)]
 pub struct Pursuit {
-    target_current_position: Vec2,
-    target_current_velocity: Vec2,
-    lookahead: f32,
+    pub target_current_position: Vec2,
+    pub target_current_velocity: Vec2,
+    pub lookahead: f32,
 }
 
 #[derive(Component, Inspectable)]
@@ -54,6 +57,12 @@ impl FollowPath {
     }
 }
 
+/// Steer along the flow field of a destination that a crowd of actors shares.
+#[derive(Component, Inspectable)]
+pub struct FollowFlowField {
+    pub destination: GridPosition,
+}
+
 #[derive(Component, Inspectable)]
 pub struct Separation;
 
@@ -1,18 +1,21 @@
 use crate::components::dna::Dna;
-use crate::components::path_finding::path::{Destination, FoundPath};
+use crate::components::path_finding::grid::Floor;
+use crate::components::path_finding::path::{Destination, FoundPath, PathPriority};
 use crate::components::steering::behaviour::{
-    Alignment, Arive, Avoid, Cohesion, Evade, Flee, FollowLeader, FollowPath, Interpose, Pursuit,
-    Seek, Separation, Wander,
+    Alignment, Arive, Avoid, Cohesion, Evade, Flee, FollowFlowField, FollowLeader, FollowPath,
+    Interpose, Pursuit, Seek, Separation, Wander,
 };
 use crate::components::steering::boid::{Acceleration, Mass, MaxForce, MaxSpeed, Velocity};
+use crate::editor::simulating;
+use crate::map::MapSize;
 use crate::systems::debug::color;
+use crate::systems::debug::path_finding::show_regions;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 use bevy::log::{Level, LogSettings};
 use bevy::prelude::*;
 use bevy_inspector_egui::{RegisterInspectable, WorldInspectorPlugin};
 use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
 use bevy_prototype_lyon::prelude::*;
-use rand::Rng;
 
 pub struct DebugPlugin;
 
@@ -25,6 +28,7 @@ impl Plugin for DebugPlugin {
                 .add_plugin(ShapePlugin)
                 .register_inspectable::<FoundPath>()
                 .register_inspectable::<Destination>()
+                .register_inspectable::<Floor>()
                 .register_inspectable::<Velocity>()
                 .register_inspectable::<Acceleration>()
                 .register_inspectable::<Mass>()
@@ -38,6 +42,7 @@ impl Plugin for DebugPlugin {
                 .register_inspectable::<Avoid>()
                 .register_inspectable::<Wander>()
                 .register_inspectable::<FollowPath>()
+                .register_inspectable::<FollowFlowField>()
                 .register_inspectable::<Separation>()
                 .register_inspectable::<Cohesion>()
                 .register_inspectable::<Alignment>()
@@ -51,7 +56,8 @@ impl Plugin for DebugPlugin {
                 })
                 .add_plugin(DebugLinesPlugin::default())
                 .add_system(render_paths)
-                .add_system(set_new_destinations);
+                .add_system(show_regions)
+                .add_system(set_new_destinations.with_run_criteria(simulating));
         }
     }
 }
@@ -96,6 +102,7 @@ fn set_new_destinations(
     keyboard: Res<Input<KeyCode>>,
     mut commands: Commands,
     entities: Query<Entity, With<FoundPath>>,
+    map_size: Res<MapSize>,
 ) {
     if !keyboard.just_pressed(KeyCode::R) {
         return;
@@ -103,13 +110,15 @@ fn set_new_destinations(
     let mut rng = rand::thread_rng();
 
     for entity in entities.iter() {
-        let x = rng.gen_range(0..255) as u32;
-        let y = rng.gen_range(0..255) as u32;
-        let destination_tile = UVec2::new(x, y);
+        let destination_tile = match map_size.random_position(&mut rng) {
+            Some(position) => position,
+            None => return,
+        };
 
         let mut entity = commands.entity(entity);
         entity.remove::<Destination>();
         entity.remove::<FoundPath>();
+        entity.insert(PathPriority::Idle);
         entity.insert(Destination(destination_tile));
     }
 }
@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+/// How a drag with the left mouse button paints tiles.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Brush {
+    /// Every tile the cursor passes over.
+    Pencil,
+    /// A straight line from where the drag started to where it ended.
+    Line,
+    /// A filled rectangle with the start and end of the drag as opposite corners.
+    Rectangle,
+    /// All tiles connected to the clicked tile that have the same glyph.
+    FloodFill,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush::Pencil
+    }
+}
+
+/// Tiles on a straight line between both ends, inclusive, using Bresenham's algorithm.
+pub fn line(from: UVec2, to: UVec2) -> Vec<UVec2> {
+    let (mut x, mut y) = (from.x as i64, from.y as i64);
+    let (to_x, to_y) = (to.x as i64, to.y as i64);
+    let dx = (to_x - x).abs();
+    let dy = -(to_y - y).abs();
+    let step_x = if x < to_x { 1 } else { -1 };
+    let step_y = if y < to_y { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    let mut tiles = Vec::new();
+    loop {
+        tiles.push(UVec2::new(x as u32, y as u32));
+        if x == to_x && y == to_y {
+            break;
+        }
+
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+
+    tiles
+}
+
+/// Tiles of the filled rectangle spanned by two opposite corners.
+pub fn rectangle(from: UVec2, to: UVec2) -> Vec<UVec2> {
+    let min = from.min(to);
+    let max = from.max(to);
+
+    (min.y..=max.y)
+        .flat_map(|y| (min.x..=max.x).map(move |x| UVec2::new(x, y)))
+        .collect()
+}
+
+/// Tiles with the same glyph as `start` that can be reached from it without moving diagonally.
+/// `glyph_at` returns `None` outside of the floor.
+pub fn flood_fill<F>(start: UVec2, glyph_at: F) -> Vec<UVec2>
+where
+    F: Fn(UVec2) -> Option<char>,
+{
+    let target = match glyph_at(start) {
+        Some(glyph) => glyph,
+        None => return Vec::new(),
+    };
+
+    let mut visited = HashSet::default();
+    let mut open = vec![start];
+    visited.insert(start);
+
+    let mut tiles = Vec::new();
+    while let Some(tile) = open.pop() {
+        tiles.push(tile);
+
+        let neighbours = [
+            tile.x.checked_sub(1).map(|x| UVec2::new(x, tile.y)),
+            Some(UVec2::new(tile.x + 1, tile.y)),
+            tile.y.checked_sub(1).map(|y| UVec2::new(tile.x, y)),
+            Some(UVec2::new(tile.x, tile.y + 1)),
+        ];
+        for neighbour in neighbours.into_iter().flatten() {
+            if glyph_at(neighbour) == Some(target) && visited.insert(neighbour) {
+                open.push(neighbour);
+            }
+        }
+    }
+
+    tiles
+}
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+
+use crate::components::path_finding::grid::GridPosition;
+
+/// A single tile painted by the editor.
+#[derive(Clone, Copy, Debug)]
+pub struct TileEdit {
+    pub position: GridPosition,
+    pub before: char,
+    pub after: char,
+}
+
+/// Edits that can be undone and redone, one entry per brush stroke.
+/// Only the most recent `capacity` strokes are kept.
+pub struct EditHistory {
+    undo: VecDeque<Vec<TileEdit>>,
+    redo: Vec<Vec<TileEdit>>,
+    capacity: usize,
+}
+
+impl EditHistory {
+    pub fn new(capacity: usize) -> Self {
+        EditHistory {
+            undo: VecDeque::with_capacity(capacity),
+            redo: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Record a new stroke, which can no longer be followed by the strokes that were undone.
+    pub fn push(&mut self, edits: Vec<TileEdit>) {
+        if edits.is_empty() || self.capacity == 0 {
+            return;
+        }
+
+        self.redo.clear();
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(edits);
+    }
+
+    /// The glyphs to paint to undo the last stroke.
+    pub fn undo(&mut self) -> Option<Vec<(GridPosition, char)>> {
+        let edits = self.undo.pop_back()?;
+        // Undo in reverse so tiles painted twice in one stroke end up as they were before it
+        let tiles = edits
+            .iter()
+            .rev()
+            .map(|edit| (edit.position, edit.before))
+            .collect();
+        self.redo.push(edits);
+        Some(tiles)
+    }
+
+    /// The glyphs to paint to redo the last undone stroke.
+    pub fn redo(&mut self) -> Option<Vec<(GridPosition, char)>> {
+        let edits = self.redo.pop()?;
+        let tiles = edits
+            .iter()
+            .map(|edit| (edit.position, edit.after))
+            .collect();
+        self.undo.push_back(edits);
+        Some(tiles)
+    }
+
+    /// Forget all strokes, the tiles they refer to may no longer exist.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
@@ -0,0 +1,295 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+use bevy_mouse_tracking_plugin::MousePosWorld;
+
+use crate::components::path_finding::grid::{Floor, Glyph, GridPosition};
+use crate::map::asset::MapDefinition;
+use crate::map::{world2d_to_grid, ActiveFloor, MapHandle, MapSize, PaintTiles};
+
+use self::brush::{flood_fill, line, rectangle, Brush};
+use self::history::{EditHistory, TileEdit};
+
+pub mod brush;
+pub mod history;
+
+/// Number of strokes that can be undone.
+const UNDO_LIMIT: usize = 100;
+
+/// Paints tiles of the active floor with the mouse.
+///
+/// Tab switches between editing and the simulation, while editing the mouse and the keys
+/// that spawn actors or give them new destinations only drive the editor.
+/// Keys 1 to 9 pick a tile type from the palette, P, L, R and F pick a brush,
+/// Ctrl+Z undoes a stroke and Ctrl+Y redoes it.
+/// Edits are sent as `PaintTiles`, so paths and the nav mesh follow them like any other tile change.
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Editor::default())
+            .insert_resource(EditHistory::new(UNDO_LIMIT))
+            .add_system(toggle_editor)
+            .add_system(forget_history_on_reload)
+            .add_system(select_tile_type.with_run_criteria(editing))
+            .add_system(select_brush.with_run_criteria(editing))
+            .add_system(undo_redo.with_run_criteria(editing))
+            .add_system(paint.with_run_criteria(editing));
+    }
+}
+
+/// State of the map editor.
+#[derive(Default)]
+pub struct Editor {
+    pub enabled: bool,
+    /// Glyph of the tile type that is painted.
+    pub glyph: Option<char>,
+    pub brush: Brush,
+    stroke: Option<Stroke>,
+}
+
+/// A drag of the left mouse button that is not finished yet.
+struct Stroke {
+    start: UVec2,
+    floor: u32,
+    edits: Vec<TileEdit>,
+    /// Tiles in `edits`, a tile is only painted once per stroke.
+    painted: HashSet<GridPosition>,
+}
+
+/// Run criteria for systems that only run while the editor is open.
+pub fn editing(editor: Res<Editor>) -> ShouldRun {
+    if editor.enabled {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Run criteria for simulation input, which is ignored while the editor is open.
+pub fn simulating(editor: Res<Editor>) -> ShouldRun {
+    if editor.enabled {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+/// The glyphs of the legend in a fixed order, the first nine can be picked with the number keys.
+fn palette(map: &MapDefinition) -> Vec<char> {
+    let mut glyphs = map.legend.keys().copied().collect::<Vec<_>>();
+    glyphs.sort_unstable();
+    glyphs
+}
+
+fn toggle_editor(
+    keyboard: Res<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    map_handle: Option<Res<MapHandle>>,
+    maps: Res<Assets<MapDefinition>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Tab) {
+        return;
+    }
+
+    editor.enabled = !editor.enabled;
+    editor.stroke = None;
+    if !editor.enabled {
+        info!("Editor closed");
+        return;
+    }
+
+    info!("Editor opened with the {:?} brush", editor.brush);
+    if let Some(map) = map_handle.and_then(|handle| maps.get(&handle.0)) {
+        for (index, glyph) in palette(map).iter().enumerate().take(9) {
+            info!("{}: {} ({:?})", index + 1, map.legend[glyph].name, glyph);
+        }
+    }
+}
+
+/// The tiles a stroke refers to may have changed on disk, so a reloaded map cannot be undone into.
+fn forget_history_on_reload(
+    mut map_events: EventReader<AssetEvent<MapDefinition>>,
+    mut editor: ResMut<Editor>,
+    mut history: ResMut<EditHistory>,
+) {
+    let reloaded = map_events
+        .iter()
+        .any(|event| matches!(event, AssetEvent::Modified { .. }));
+
+    if reloaded {
+        editor.stroke = None;
+        history.clear();
+    }
+}
+
+fn select_tile_type(
+    keyboard: Res<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    map_handle: Option<Res<MapHandle>>,
+    maps: Res<Assets<MapDefinition>>,
+) {
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+
+    let index = match KEYS.iter().position(|key| keyboard.just_pressed(*key)) {
+        Some(index) => index,
+        None => return,
+    };
+    let map = match map_handle.and_then(|handle| maps.get(&handle.0)) {
+        Some(map) => map,
+        None => return,
+    };
+
+    if let Some(glyph) = palette(map).get(index) {
+        info!("Painting {} ({:?})", map.legend[glyph].name, glyph);
+        editor.glyph = Some(*glyph);
+    }
+}
+
+fn select_brush(keyboard: Res<Input<KeyCode>>, mut editor: ResMut<Editor>) {
+    let brush = if keyboard.just_pressed(KeyCode::P) {
+        Brush::Pencil
+    } else if keyboard.just_pressed(KeyCode::L) {
+        Brush::Line
+    } else if keyboard.just_pressed(KeyCode::R) {
+        Brush::Rectangle
+    } else if keyboard.just_pressed(KeyCode::F) {
+        Brush::FloodFill
+    } else {
+        return;
+    };
+
+    info!("Using the {:?} brush", brush);
+    editor.brush = brush;
+    editor.stroke = None;
+}
+
+fn undo_redo(
+    keyboard: Res<Input<KeyCode>>,
+    mut history: ResMut<EditHistory>,
+    mut paints: EventWriter<PaintTiles>,
+) {
+    if !keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        return;
+    }
+
+    let tiles = if keyboard.just_pressed(KeyCode::Z) {
+        history.undo()
+    } else if keyboard.just_pressed(KeyCode::Y) {
+        history.redo()
+    } else {
+        return;
+    };
+
+    if let Some(tiles) = tiles {
+        paints.send(PaintTiles(tiles));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn paint(
+    mouse: Res<Input<MouseButton>>,
+    mouse_pos: Res<MousePosWorld>,
+    active_floor: Res<ActiveFloor>,
+    map_size: Res<MapSize>,
+    tilemap_query: Query<(&Floor, &TileStorage)>,
+    glyph_query: Query<&Glyph>,
+    mut editor: ResMut<Editor>,
+    mut history: ResMut<EditHistory>,
+    mut paints: EventWriter<PaintTiles>,
+) {
+    let (glyph, brush) = match editor.glyph {
+        Some(glyph) => (glyph, editor.brush),
+        None => return,
+    };
+    let storage = match tilemap_query
+        .iter()
+        .find(|(floor, _)| floor.0 == active_floor.0)
+    {
+        Some((_, storage)) => storage,
+        None => return,
+    };
+    let glyph_at = |tile: UVec2| {
+        if !map_size.contains(&GridPosition::new(tile, active_floor.0)) {
+            return None;
+        }
+        let glyph = glyph_query.get(storage.get(&TilePos::from(tile))?).ok()?;
+        Some(glyph.0)
+    };
+
+    let hovered = world2d_to_grid(&mouse_pos.truncate()).filter(|tile| glyph_at(*tile).is_some());
+    // Lines and rectangles dragged past the edge of the map end at the edge
+    let cursor = world2d_to_grid(&mouse_pos.truncate().max(Vec2::ZERO))
+        .unwrap_or_default()
+        .min(map_size.tiles.max(UVec2::ONE) - UVec2::ONE);
+
+    if mouse.just_pressed(MouseButton::Left) {
+        editor.stroke = hovered.map(|start| Stroke {
+            start,
+            floor: active_floor.0,
+            edits: Vec::new(),
+            painted: HashSet::default(),
+        });
+    }
+
+    let stroke = match editor.stroke.as_mut() {
+        Some(stroke) => stroke,
+        None => return,
+    };
+    if stroke.floor != active_floor.0 {
+        editor.stroke = None;
+        return;
+    }
+
+    let released = mouse.just_released(MouseButton::Left) || !mouse.pressed(MouseButton::Left);
+    let tiles = match brush {
+        Brush::Pencil => hovered.into_iter().collect(),
+        Brush::Line if released => line(stroke.start, cursor),
+        Brush::Rectangle if released => rectangle(stroke.start, cursor),
+        Brush::FloodFill if mouse.just_pressed(MouseButton::Left) => {
+            flood_fill(stroke.start, glyph_at)
+        }
+        _ => Vec::new(),
+    };
+
+    let mut painted = Vec::new();
+    for tile in tiles {
+        let position = GridPosition::new(tile, active_floor.0);
+        let before = match glyph_at(tile) {
+            Some(before) => before,
+            None => continue,
+        };
+        // A tile painted earlier in the stroke still shows its old glyph until the paint is applied
+        if before == glyph || !stroke.painted.insert(position) {
+            continue;
+        }
+
+        stroke.edits.push(TileEdit {
+            position,
+            before,
+            after: glyph,
+        });
+        painted.push((position, glyph));
+    }
+
+    if !painted.is_empty() {
+        paints.send(PaintTiles(painted));
+    }
+
+    if released {
+        if let Some(stroke) = editor.stroke.take() {
+            history.push(stroke.edits);
+        }
+    }
+}
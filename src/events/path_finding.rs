@@ -0,0 +1,64 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::components::path_finding::grid::GridPosition;
+
+/// Sent when a search is queued for an actor.
+pub struct PathRequested {
+    pub entity: Entity,
+    pub from: GridPosition,
+    pub to: GridPosition,
+}
+
+/// Sent when a path was found and handed to the actor, or when the flow field an actor follows
+/// is ready.
+pub struct PathFound {
+    pub entity: Entity,
+    /// Last tile of the path. It is not the requested destination when the destination was
+    /// unreachable and `RequestFallbacks::closest_reachable` is set.
+    pub destination: GridPosition,
+    /// Cost of the path, in the units of `STRAIGHT_COST`.
+    pub cost: u32,
+    /// Number of tiles on the path, including the start and the destination.
+    pub length: usize,
+}
+
+/// Sent when no path could be found for an actor.
+pub struct PathFailed {
+    pub entity: Entity,
+    pub reason: PathFailure,
+}
+
+/// Sent when an actor arrived at its destination, after `Destination` and the path or flow field
+/// it followed are removed.
+pub struct DestinationReached {
+    pub entity: Entity,
+    pub destination: GridPosition,
+}
+
+/// Why a search did not find a path.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathFailure {
+    /// The destination cannot be reached from the start.
+    Unreachable,
+    /// The start or the destination is outside the map.
+    OutOfBounds,
+    /// The actor is standing on a tile that cannot be walked on.
+    StartBlocked,
+    /// The search took longer than the scheduler allows.
+    TimedOut,
+}
+
+impl fmt::Display for PathFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathFailure::Unreachable => write!(f, "destination is unreachable"),
+            PathFailure::OutOfBounds => write!(f, "start or destination is outside the map"),
+            PathFailure::StartBlocked => write!(f, "start is not walkable"),
+            PathFailure::TimedOut => write!(f, "search timed out"),
+        }
+    }
+}
+
+impl std::error::Error for PathFailure {}
@@ -0,0 +1,20 @@
+#![allow(clippy::redundant_field_names)]
+
+use bevy::prelude::*;
+
+pub mod actor;
+pub mod camera;
+pub mod components;
+pub mod debug;
+pub mod editor;
+pub mod events;
+pub mod map;
+pub mod plugins;
+pub mod resources;
+pub mod snapshot;
+pub mod systems;
+
+pub const CLEAR: Color = Color::rgb(0.1, 0.1, 0.1);
+pub const RESOLUTION: f32 = 16.0 / 9.0;
+pub const TILE_SIZE: f32 = 16.0;
+pub const TILE_SIZE_DIAGONAL: f32 = 22.6;
@@ -1,29 +1,18 @@
 #![allow(clippy::redundant_field_names)]
 
+use bevy::asset::AssetServerSettings;
 use bevy::prelude::*;
 use bevy_mouse_tracking_plugin::MousePosPlugin;
 
-use debug::DebugPlugin;
-use systems::{drawing::drawing, map::set_texture_filters_to_nearest};
-
-use crate::actor::ActorPlugin;
-use camera::CameraPlugin;
-use map::TileMapPlugin;
-use plugins::{path_finding::PathFindingPlugin, steering::SteeringPlugin};
-
-mod actor;
-mod camera;
-mod components;
-mod debug;
-mod map;
-mod plugins;
-mod resources;
-mod systems;
-
-pub const CLEAR: Color = Color::rgb(0.1, 0.1, 0.1);
-pub const RESOLUTION: f32 = 16.0 / 9.0;
-pub const TILE_SIZE: f32 = 16.0;
-pub const TILE_SIZE_DIAGONAL: f32 = 22.6;
+use sim_something::actor::ActorPlugin;
+use sim_something::camera::CameraPlugin;
+use sim_something::debug::DebugPlugin;
+use sim_something::editor::EditorPlugin;
+use sim_something::map::TileMapPlugin;
+use sim_something::plugins::{path_finding::PathFindingPlugin, steering::SteeringPlugin};
+use sim_something::snapshot::SnapshotPlugin;
+use sim_something::systems::map::set_texture_filters_to_nearest;
+use sim_something::{CLEAR, RESOLUTION};
 
 fn main() {
     let height = 400.0;
@@ -40,17 +29,23 @@ fn main() {
             resizable: true,
             ..Default::default()
         })
+        // Reload changed map files while the simulation is running
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..Default::default()
+        })
         // .add_startup_system(spawn_camera)
         .add_plugins(DefaultPlugins)
         // .add_plugin(AsciiPlugin)
         .add_plugin(ActorPlugin)
-        .add_plugin(PathFindingPlugin)
+        .add_plugin(PathFindingPlugin::default())
         .add_plugin(CameraPlugin)
         .add_plugin(TileMapPlugin)
         .add_system(set_texture_filters_to_nearest)
         .add_plugin(DebugPlugin)
         .add_plugin(SteeringPlugin)
         .add_plugin(MousePosPlugin::SingleCamera)
-        .add_system(drawing)
+        .add_plugin(EditorPlugin)
+        .add_plugin(SnapshotPlugin)
         .run();
 }
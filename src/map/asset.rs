@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bevy::asset::{AssetIoError, AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::components::path_finding::grid::GridPosition;
+
+/// A map of the building, loaded from a `.map.ron` file.
+///
+/// The file describes the map size, a legend that maps each glyph to a tile type,
+/// the text files with the layout of each floor, spawn points and named zones.
+/// Floor files are read relative to the assets folder.
+/// When the size is left out, it is taken from the widest and tallest floor.
+#[derive(TypeUuid, Debug)]
+#[uuid = "0f5b3c55-3c2f-4f0d-9a8e-6f1c1a0e7d42"]
+pub struct MapDefinition {
+    pub name: String,
+    pub size: UVec2,
+    pub legend: HashMap<char, TileType>,
+    pub floors: Vec<FloorLayout>,
+    pub spawn_points: Vec<GridPosition>,
+    pub zones: Vec<Zone>,
+}
+
+impl MapDefinition {
+    /// Type of the tile at the given position, if there is one.
+    pub fn tile_type(&self, position: &GridPosition) -> Option<&TileType> {
+        let glyph = self
+            .floors
+            .get(position.floor as usize)?
+            .glyph(position.tile)?;
+        self.legend.get(&glyph)
+    }
+
+    /// A random walkable tile in a random zone, `None` if the zone has none or there are no zones.
+    pub fn random_zone_tile<R: Rng>(&self, rng: &mut R) -> Option<GridPosition> {
+        let zone = self.zones.choose(rng)?;
+        let walkable = zone
+            .tiles()
+            .filter(|position| self.tile_type(position).map_or(false, |tile| tile.walkable))
+            .collect::<Vec<_>>();
+        walkable.choose(rng).copied()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TileType {
+    pub name: String,
+    pub texture: u32,
+    #[serde(default)]
+    pub walkable: bool,
+    /// Multiplier on the cost of moving onto this tile.
+    #[serde(default = "default_cost")]
+    pub cost: f32,
+    #[serde(default)]
+    pub stairs: Option<Stairs>,
+}
+
+fn default_cost() -> f32 {
+    1.0
+}
+
+/// Direction of a staircase.
+/// A staircase up connects to the staircase down at the same position on the floor above.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stairs {
+    Up,
+    Down,
+}
+
+/// The glyphs of a single floor, one row per line of the floor file.
+#[derive(Debug)]
+pub struct FloorLayout {
+    pub path: String,
+    pub rows: Vec<Vec<char>>,
+}
+
+impl FloorLayout {
+    pub fn size(&self) -> UVec2 {
+        let width = self.rows.first().map_or(0, |row| row.len());
+        UVec2::new(width as u32, self.rows.len() as u32)
+    }
+
+    pub fn glyph(&self, tile: UVec2) -> Option<char> {
+        self.rows
+            .get(tile.y as usize)?
+            .get(tile.x as usize)
+            .copied()
+    }
+}
+
+/// A named area on a single floor, with both corners inclusive.
+#[derive(Debug)]
+pub struct Zone {
+    pub name: String,
+    pub floor: u32,
+    pub min: UVec2,
+    pub max: UVec2,
+}
+
+impl Zone {
+    pub fn tiles(&self) -> impl Iterator<Item = GridPosition> + '_ {
+        (self.min.y..=self.max.y).flat_map(move |y| {
+            (self.min.x..=self.max.x).map(move |x| GridPosition::new(UVec2::new(x, y), self.floor))
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct MapFile {
+    name: String,
+    #[serde(default)]
+    size: Option<(u32, u32)>,
+    legend: HashMap<char, TileType>,
+    floors: Vec<String>,
+    #[serde(default)]
+    spawn_points: Vec<PositionFile>,
+    #[serde(default)]
+    zones: Vec<ZoneFile>,
+}
+
+#[derive(Deserialize)]
+struct PositionFile {
+    tile: (u32, u32),
+    floor: u32,
+}
+
+#[derive(Deserialize)]
+struct ZoneFile {
+    name: String,
+    floor: u32,
+    from: (u32, u32),
+    to: (u32, u32),
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Syntax(ron::Error),
+    InvalidCost {
+        glyph: char,
+        cost: f32,
+    },
+    MissingFloor {
+        path: String,
+        error: AssetIoError,
+    },
+    Encoding {
+        path: String,
+    },
+    UnknownGlyph {
+        path: String,
+        line: usize,
+        column: usize,
+        glyph: char,
+    },
+    RaggedRow {
+        path: String,
+        line: usize,
+        column: usize,
+        expected: usize,
+        found: usize,
+    },
+    TooLarge {
+        path: String,
+        line: usize,
+        column: usize,
+        size: UVec2,
+    },
+    SpawnPointOutside {
+        position: GridPosition,
+    },
+    SpawnPointBlocked {
+        position: GridPosition,
+    },
+    ZoneOutside {
+        name: String,
+    },
+    ZoneBlocked {
+        name: String,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Syntax(error) => write!(f, "invalid map definition: {}", error),
+            MapError::InvalidCost { glyph, cost } => write!(
+                f,
+                "tile type {:?} has cost {}, costs must be positive",
+                glyph, cost
+            ),
+            MapError::MissingFloor { path, error } => {
+                write!(f, "{}: could not read floor: {}", path, error)
+            }
+            MapError::Encoding { path } => write!(f, "{}: floor is not valid UTF-8", path),
+            MapError::UnknownGlyph {
+                path,
+                line,
+                column,
+                glyph,
+            } => write!(
+                f,
+                "{}:{}:{}: glyph {:?} is not in the legend",
+                path, line, column, glyph
+            ),
+            MapError::RaggedRow {
+                path,
+                line,
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}:{}:{}: row is {} tiles wide, expected {}",
+                path, line, column, found, expected
+            ),
+            MapError::TooLarge {
+                path,
+                line,
+                column,
+                size,
+            } => write!(
+                f,
+                "{}:{}:{}: floor does not fit in a map of {}x{} tiles",
+                path, line, column, size.x, size.y
+            ),
+            MapError::SpawnPointOutside { position } => write!(
+                f,
+                "spawn point {} on floor {} is outside the floor",
+                position.tile, position.floor
+            ),
+            MapError::SpawnPointBlocked { position } => write!(
+                f,
+                "spawn point {} on floor {} is not walkable",
+                position.tile, position.floor
+            ),
+            MapError::ZoneOutside { name } => {
+                write!(f, "zone {:?} reaches outside its floor", name)
+            }
+            MapError::ZoneBlocked { name } => {
+                write!(f, "zone {:?} has no walkable tiles", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let file: MapFile = ron::de::from_bytes(bytes).map_err(MapError::Syntax)?;
+            for (glyph, tile_type) in &file.legend {
+                if !tile_type.cost.is_finite() || tile_type.cost <= 0.0 {
+                    return Err(MapError::InvalidCost {
+                        glyph: *glyph,
+                        cost: tile_type.cost,
+                    }
+                    .into());
+                }
+            }
+            let size = file.size.map(|(x, y)| UVec2::new(x, y));
+
+            let mut floors = Vec::with_capacity(file.floors.len());
+            for path in file.floors {
+                let bytes = load_context
+                    .read_asset_bytes(&path)
+                    .await
+                    .map_err(|error| MapError::MissingFloor {
+                        path: path.clone(),
+                        error,
+                    })?;
+                floors.push(parse_floor(path, &bytes, &file.legend, size)?);
+            }
+
+            let size = size.unwrap_or_else(|| {
+                floors
+                    .iter()
+                    .fold(UVec2::ZERO, |size, floor| size.max(floor.size()))
+            });
+
+            let map = MapDefinition {
+                name: file.name,
+                size,
+                legend: file.legend,
+                floors,
+                spawn_points: file
+                    .spawn_points
+                    .into_iter()
+                    .map(|spawn| {
+                        GridPosition::new(UVec2::new(spawn.tile.0, spawn.tile.1), spawn.floor)
+                    })
+                    .collect(),
+                zones: file
+                    .zones
+                    .into_iter()
+                    .map(|zone| {
+                        let from = UVec2::new(zone.from.0, zone.from.1);
+                        let to = UVec2::new(zone.to.0, zone.to.1);
+                        Zone {
+                            name: zone.name,
+                            floor: zone.floor,
+                            min: from.min(to),
+                            max: from.max(to),
+                        }
+                    })
+                    .collect(),
+            };
+            check_places(&map)?;
+
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.ron"]
+    }
+}
+
+/// Placeholder asset for a floor file.
+/// The floors are read by the `MapLoader`, they are only loaded on their own so the
+/// asset server watches them for changes.
+#[derive(TypeUuid)]
+#[uuid = "7c1d2a4e-93b5-4c8f-8e2a-5d6b0f3e1c97"]
+pub struct FloorSource;
+
+#[derive(Default)]
+pub struct FloorSourceLoader;
+
+impl AssetLoader for FloorSourceLoader {
+    fn load<'a>(
+        &'a self,
+        _bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(FloorSource));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+}
+
+/// Checks that every spawn point is on a walkable tile and every zone lies within its floor
+/// with at least one walkable tile to go to.
+fn check_places(map: &MapDefinition) -> Result<(), MapError> {
+    let walkable = |position: &GridPosition| map.tile_type(position).map(|tile| tile.walkable);
+
+    for position in &map.spawn_points {
+        match walkable(position) {
+            None => {
+                return Err(MapError::SpawnPointOutside {
+                    position: *position,
+                })
+            }
+            Some(false) => {
+                return Err(MapError::SpawnPointBlocked {
+                    position: *position,
+                })
+            }
+            Some(true) => {}
+        }
+    }
+
+    for zone in &map.zones {
+        // Floors are rectangular, so the far corner is inside when the whole zone is
+        if walkable(&GridPosition::new(zone.max, zone.floor)).is_none() {
+            return Err(MapError::ZoneOutside {
+                name: zone.name.clone(),
+            });
+        }
+        if !zone
+            .tiles()
+            .any(|position| walkable(&position) == Some(true))
+        {
+            return Err(MapError::ZoneBlocked {
+                name: zone.name.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a floor file, checking every glyph against the legend.
+/// Lines and columns in the errors start at one, like in a text editor.
+fn parse_floor(
+    path: String,
+    bytes: &[u8],
+    legend: &HashMap<char, TileType>,
+    size: Option<UVec2>,
+) -> Result<FloorLayout, MapError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| MapError::Encoding { path: path.clone() })?;
+
+    let mut rows: Vec<Vec<char>> = Vec::new();
+    for (y, line) in text.lines().enumerate() {
+        let row = line.chars().collect::<Vec<char>>();
+
+        if let Some((x, glyph)) = row
+            .iter()
+            .enumerate()
+            .find(|(_, glyph)| !legend.contains_key(glyph))
+        {
+            return Err(MapError::UnknownGlyph {
+                path,
+                line: y + 1,
+                column: x + 1,
+                glyph: *glyph,
+            });
+        }
+
+        if let Some(first) = rows.first() {
+            if first.len() != row.len() {
+                return Err(MapError::RaggedRow {
+                    path,
+                    line: y + 1,
+                    column: first.len().min(row.len()) + 1,
+                    expected: first.len(),
+                    found: row.len(),
+                });
+            }
+        }
+
+        if let Some(size) = size {
+            if row.len() > size.x as usize || y >= size.y as usize {
+                return Err(MapError::TooLarge {
+                    path,
+                    line: y + 1,
+                    column: row.len().min(size.x as usize) + 1,
+                    size,
+                });
+            }
+        }
+
+        rows.push(row);
+    }
+
+    Ok(FloorLayout { path, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legend() -> HashMap<char, TileType> {
+        let tile_type = |name: &str, walkable| TileType {
+            name: name.to_string(),
+            texture: 0,
+            walkable,
+            cost: 1.0,
+            stairs: None,
+        };
+        HashMap::from([
+            ('.', tile_type("floor", true)),
+            ('#', tile_type("wall", false)),
+        ])
+    }
+
+    fn floor(text: &str) -> Result<FloorLayout, MapError> {
+        parse_floor("floor.txt".to_string(), text.as_bytes(), &legend(), None)
+    }
+
+    fn map(text: &str, spawn_points: Vec<GridPosition>, zones: Vec<Zone>) -> MapDefinition {
+        let floor = floor(text).unwrap();
+        MapDefinition {
+            name: "Test".to_string(),
+            size: floor.size(),
+            legend: legend(),
+            floors: vec![floor],
+            spawn_points,
+            zones,
+        }
+    }
+
+    fn zone(min: (u32, u32), max: (u32, u32)) -> Zone {
+        Zone {
+            name: "Office".to_string(),
+            floor: 0,
+            min: UVec2::new(min.0, min.1),
+            max: UVec2::new(max.0, max.1),
+        }
+    }
+
+    #[test]
+    fn unknown_glyph_reports_its_line_and_column() {
+        match floor("...\n.x.\n") {
+            Err(MapError::UnknownGlyph {
+                line,
+                column,
+                glyph,
+                ..
+            }) => assert_eq!((line, column, glyph), (2, 2, 'x')),
+            other => panic!("expected an unknown glyph, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ragged_row_reports_where_it_ends() {
+        match floor("...\n...\n..\n") {
+            Err(MapError::RaggedRow {
+                line,
+                column,
+                expected,
+                found,
+                ..
+            }) => assert_eq!((line, column, expected, found), (3, 3, 3, 2)),
+            other => panic!("expected a ragged row, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_floor_has_no_tiles() {
+        let floor = floor("").unwrap();
+
+        assert!(floor.rows.is_empty());
+        assert_eq!(floor.size(), UVec2::ZERO);
+        assert_eq!(floor.glyph(UVec2::ZERO), None);
+    }
+
+    #[test]
+    fn spawn_points_have_to_be_walkable_tiles_on_the_floor() {
+        let at = |x, y, floor| GridPosition::new(UVec2::new(x, y), floor);
+
+        assert!(check_places(&map("..\n.#\n", vec![at(0, 1, 0)], vec![])).is_ok());
+        assert!(matches!(
+            check_places(&map("..\n.#\n", vec![at(1, 1, 0)], vec![])),
+            Err(MapError::SpawnPointBlocked { .. })
+        ));
+        assert!(matches!(
+            check_places(&map("..\n.#\n", vec![at(2, 0, 0)], vec![])),
+            Err(MapError::SpawnPointOutside { .. })
+        ));
+        assert!(matches!(
+            check_places(&map("..\n.#\n", vec![at(0, 0, 1)], vec![])),
+            Err(MapError::SpawnPointOutside { .. })
+        ));
+    }
+
+    #[test]
+    fn zones_have_to_fit_the_floor_and_have_a_walkable_tile() {
+        assert!(check_places(&map("#.\n##\n", vec![], vec![zone((0, 0), (1, 1))])).is_ok());
+        assert!(matches!(
+            check_places(&map("#.\n##\n", vec![], vec![zone((0, 0), (2, 1))])),
+            Err(MapError::ZoneOutside { .. })
+        ));
+        assert!(matches!(
+            check_places(&map("#.\n##\n", vec![], vec![zone((0, 1), (1, 1))])),
+            Err(MapError::ZoneBlocked { .. })
+        ));
+    }
+}
@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_ecs_tilemap::map::TilemapSize;
+use bevy_ecs_tilemap::tiles::TileStorage;
+
+use crate::components::path_finding::grid::{Floor, Glyph};
+
+use super::asset::MapDefinition;
+use super::{floor_rows, MapHandle};
+
+const ASSET_FOLDER: &str = "assets";
+
+/// Write the current tiles of every floor back to the floor files of the map.
+pub fn export_map(
+    keyboard: Res<Input<KeyCode>>,
+    map_handle: Option<Res<MapHandle>>,
+    maps: Res<Assets<MapDefinition>>,
+    tilemap_query: Query<(&Floor, &TilemapSize, &TileStorage)>,
+    glyph_query: Query<&Glyph>,
+) {
+    if !keyboard.just_pressed(KeyCode::F6) {
+        return;
+    }
+
+    let map = match map_handle.and_then(|handle| maps.get(&handle.0)) {
+        Some(map) => map,
+        None => return,
+    };
+
+    for (floor, size, storage) in tilemap_query.iter() {
+        let layout = match map.floors.get(floor.0 as usize) {
+            Some(layout) => layout,
+            None => continue,
+        };
+
+        let mut text = String::new();
+        for row in floor_rows(size, storage, &glyph_query) {
+            text.push_str(&row);
+            text.push('\n');
+        }
+
+        match fs::write(Path::new(ASSET_FOLDER).join(&layout.path), text) {
+            Ok(()) => info!("Exported floor {} to {}", floor.0, layout.path),
+            Err(error) => error!("Could not export floor {}: {}", floor.0, error),
+        }
+    }
+}
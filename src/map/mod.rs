@@ -0,0 +1,456 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ecs_tilemap::map::{
+    TilemapGridSize, TilemapId, TilemapSize, TilemapTexture, TilemapTextureSize, TilemapTileSize,
+};
+use bevy_ecs_tilemap::tiles::{TileBundle, TilePos, TileStorage, TileTexture};
+use bevy_ecs_tilemap::{TilemapBundle, TilemapPlugin};
+use rand::Rng;
+
+use crate::components::path_finding::grid::{
+    Floor, Glyph, GridPosition, Staircase, TerrainCost, Walkable,
+};
+use crate::TILE_SIZE;
+
+use self::asset::{FloorLayout, FloorSource, FloorSourceLoader, MapDefinition, MapLoader, Stairs};
+use self::export::export_map;
+use self::paint::paint_tiles;
+pub use self::paint::PaintTiles;
+use self::reload::{reload_map, reload_on_floor_change, watch_floor_sources, FloorSources};
+
+pub mod asset;
+mod export;
+mod paint;
+pub mod raycast;
+mod reload;
+
+pub struct TileMapPlugin;
+
+impl Plugin for TileMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MapDefinition>()
+            .init_asset_loader::<MapLoader>()
+            .add_asset::<FloorSource>()
+            .init_asset_loader::<FloorSourceLoader>()
+            .add_event::<TileChanged>()
+            .add_event::<PaintTiles>()
+            .insert_resource(ActiveFloor::default())
+            .insert_resource(MapSize::default())
+            .insert_resource(FloorSources::default())
+            .add_startup_system(load_map)
+            .add_system(spawn_map)
+            .add_system(reload_map)
+            .add_system(watch_floor_sources)
+            .add_system(reload_on_floor_change)
+            .add_system(export_map)
+            .add_system(paint_tiles)
+            .add_system(update_tile_storage)
+            .add_system(switch_active_floor)
+            .add_system(show_active_floor.after(switch_active_floor))
+            // .add_system_to_stage(CoreState::PostUpdate, remove_tiles_from_storage)
+            .add_plugin(TilemapPlugin);
+    }
+}
+
+// TODO store previous position in another component to be able to
+// synthesize a pos for deletion from the storage?
+fn remove_tiles_from_storage(
+    removals: RemovedComponents<TilePos>,
+    mut map_query: Query<&mut TileStorage>,
+) {
+    for _map in map_query.iter_mut() {}
+    for _entity in removals.iter() {}
+}
+
+fn update_tile_storage(
+    mut commands: Commands,
+    tiles_query: Query<(Entity, &TilePos, &TilemapId), Changed<TilePos>>,
+    mut map_query: Query<&mut TileStorage>,
+) {
+    for (tile_entity, tile_position, tile_map) in tiles_query.iter() {
+        if let Ok(mut map) = map_query.get_mut(tile_map.0) {
+            map.set(tile_position, Some(tile_entity));
+            commands.entity(tile_map.0).add_child(tile_entity);
+        }
+    }
+}
+
+const MAP_PATH: &str = "building.map.ron";
+
+/// The floor that is shown and edited.
+#[derive(Default)]
+pub struct ActiveFloor(pub u32);
+
+/// Sent when the walkability or the staircase of a tile changed.
+pub struct TileChanged(pub GridPosition);
+
+/// Size of the loaded map, every floor has the same number of tiles.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct MapSize {
+    pub tiles: UVec2,
+    pub floors: u32,
+}
+
+impl MapSize {
+    pub fn contains(&self, position: &GridPosition) -> bool {
+        position.floor < self.floors && position.tile.cmplt(self.tiles).all()
+    }
+
+    /// A random tile on a random floor, or `None` while no map is loaded.
+    pub fn random_position<R: Rng>(&self, rng: &mut R) -> Option<GridPosition> {
+        if self.floors == 0 || self.tiles.x == 0 || self.tiles.y == 0 {
+            return None;
+        }
+
+        let x = rng.gen_range(0..self.tiles.x);
+        let y = rng.gen_range(0..self.tiles.y);
+        let floor = rng.gen_range(0..self.floors);
+
+        Some(GridPosition::new(UVec2::new(x, y), floor))
+    }
+}
+
+impl From<&MapDefinition> for MapSize {
+    fn from(map: &MapDefinition) -> Self {
+        MapSize {
+            tiles: map.size,
+            floors: map.floors.len() as u32,
+        }
+    }
+}
+
+/// Keeps the map definition loaded for as long as the simulation runs.
+pub struct MapHandle(pub Handle<MapDefinition>);
+
+fn load_map(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(MapHandle(asset_server.load(MAP_PATH)));
+}
+
+fn spawn_map(
+    mut commands: Commands,
+    mut map_events: EventReader<AssetEvent<MapDefinition>>,
+    maps: Res<Assets<MapDefinition>>,
+    asset_server: Res<AssetServer>,
+    mut map_size: ResMut<MapSize>,
+) {
+    for event in map_events.iter() {
+        if let AssetEvent::Created { handle } = event {
+            if let Some(map) = maps.get(handle) {
+                *map_size = MapSize::from(map);
+                create_map(&mut commands, &asset_server, map);
+            }
+        }
+    }
+}
+
+fn create_map(commands: &mut Commands, asset_server: &AssetServer, map: &MapDefinition) {
+    let staircases = link_staircases(map);
+
+    for floor in 0..map.floors.len() {
+        create_floor(commands, asset_server, map, floor as u32, &staircases);
+    }
+}
+
+fn create_floor(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    map: &MapDefinition,
+    floor: u32,
+    staircases: &HashMap<GridPosition, GridPosition>,
+) {
+    let texture_handle: Handle<Image> = asset_server.load("tiles_new.png");
+    let name = format!("Level {}", floor + 1);
+    let map_entity = create_map_entity(&name, commands, texture_handle, map.size, floor);
+
+    create_tile_entities(
+        commands,
+        map,
+        &map.floors[floor as usize],
+        map_entity,
+        floor,
+        staircases,
+    );
+}
+
+/// Pairs each staircase up with the staircase down at the same position on the floor above.
+/// The returned map contains both directions of every staircase.
+fn link_staircases(map: &MapDefinition) -> HashMap<GridPosition, GridPosition> {
+    let mut staircases = HashMap::new();
+    let glyph_at = |position: &GridPosition| {
+        map.floors
+            .get(position.floor as usize)?
+            .glyph(position.tile)
+    };
+
+    for (floor, layout) in map.floors.iter().enumerate() {
+        for (y, line) in layout.rows.iter().enumerate() {
+            for (x, glyph) in line.iter().enumerate() {
+                let stairs = match map.legend[glyph].stairs {
+                    Some(stairs) => stairs,
+                    None => continue,
+                };
+
+                let position = GridPosition::new(UVec2::new(x as u32, y as u32), floor as u32);
+                match (staircase_link(map, &position, glyph_at), stairs) {
+                    (Some(top), Stairs::Up) => {
+                        staircases.insert(position, top);
+                        staircases.insert(top, position);
+                    }
+                    // Linked from the bottom of the staircase
+                    (Some(_), Stairs::Down) => {}
+                    (None, Stairs::Up) => warn!(
+                        "Staircase at {} on floor {} has no way down on the floor above",
+                        position.tile, floor
+                    ),
+                    (None, Stairs::Down) => warn!(
+                        "Staircase at {} on floor {} has no way up on the floor below",
+                        position.tile, floor
+                    ),
+                }
+            }
+        }
+    }
+
+    staircases
+}
+
+/// The tile the staircase at `position` leads to, if there is a staircase in the opposite
+/// direction at the same position on the floor above or below.
+fn staircase_link<F>(
+    map: &MapDefinition,
+    position: &GridPosition,
+    glyph_at: F,
+) -> Option<GridPosition>
+where
+    F: Fn(&GridPosition) -> Option<char>,
+{
+    let stairs = map.legend.get(&glyph_at(position)?)?.stairs?;
+    let (to, opposite) = match stairs {
+        Stairs::Up => (position.floor + 1, Stairs::Down),
+        Stairs::Down => (position.floor.checked_sub(1)?, Stairs::Up),
+    };
+    let to = GridPosition::new(position.tile, to);
+
+    if map.legend.get(&glyph_at(&to)?)?.stairs == Some(opposite) {
+        Some(to)
+    } else {
+        None
+    }
+}
+
+fn create_map_entity(
+    name: &str,
+    commands: &mut Commands,
+    texture_handle: Handle<Image>,
+    size: UVec2,
+    floor: u32,
+) -> Entity {
+    let tilemap_size = TilemapSize {
+        x: size.x,
+        y: size.y,
+    };
+    let tile_size = TilemapTileSize { x: 16.0, y: 16.0 };
+
+    let map = commands
+        .spawn()
+        .insert(Name::from(name))
+        .insert(Floor(floor))
+        .insert_bundle(TilemapBundle {
+            grid_size: TilemapGridSize { x: 16.0, y: 16.0 },
+            size: tilemap_size,
+            storage: TileStorage::empty(tilemap_size),
+            texture_size: TilemapTextureSize { x: 96.0, y: 16.0 },
+            texture: TilemapTexture(texture_handle),
+            tile_size: tile_size,
+            transform: Transform {
+                translation: Vec3::new(0.0, 0.0, floor as f32),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+    map
+}
+
+fn create_tile_entities(
+    commands: &mut Commands,
+    map: &MapDefinition,
+    floor_layout: &FloorLayout,
+    map_entity: Entity,
+    floor: u32,
+    staircases: &HashMap<GridPosition, GridPosition>,
+) {
+    for (y, line) in floor_layout.rows.iter().enumerate() {
+        for (x, glyph) in line.iter().enumerate() {
+            let position = UVec2::new(x as u32, y as u32);
+            let staircase = staircases.get(&GridPosition::new(position, floor));
+
+            spawn_tile(commands, map, *glyph, position, map_entity, staircase);
+        }
+    }
+}
+
+fn spawn_tile(
+    commands: &mut Commands,
+    map: &MapDefinition,
+    glyph: char,
+    position: UVec2,
+    map_entity: Entity,
+    staircase: Option<&GridPosition>,
+) -> Entity {
+    // The loader rejects floors with glyphs that are not in the legend
+    let tile_type = &map.legend[&glyph];
+    let mut entity = commands.spawn();
+    entity
+        .insert_bundle(TileBundle {
+            position: TilePos::from(position),
+            texture: TileTexture(tile_type.texture),
+            tilemap_id: TilemapId(map_entity),
+            ..Default::default()
+        })
+        .insert(Glyph(glyph));
+
+    if tile_type.walkable {
+        entity.insert(Walkable::default());
+    }
+
+    if tile_type.cost != 1.0 {
+        entity.insert(TerrainCost(tile_type.cost));
+    }
+
+    if let Some(to) = staircase {
+        entity.insert(Staircase { to: *to });
+    }
+
+    entity.id()
+}
+
+/// The parts of a spawned tile that change when it is turned into another tile type.
+pub type TileState<'a> = (
+    &'a Glyph,
+    &'a TileTexture,
+    Option<&'a Walkable>,
+    Option<&'a TerrainCost>,
+    Option<&'a Staircase>,
+);
+
+/// Turn a spawned tile into the tile type of `glyph`.
+/// Returns whether its walkability, cost or staircase changed.
+fn change_tile(
+    commands: &mut Commands,
+    entity: Entity,
+    (current_glyph, texture, walkable, current_cost, current_staircase): TileState,
+    map: &MapDefinition,
+    glyph: char,
+    staircase: Option<&GridPosition>,
+) -> bool {
+    let tile_type = &map.legend[&glyph];
+    let mut tile = commands.entity(entity);
+
+    if current_glyph.0 != glyph {
+        tile.insert(Glyph(glyph));
+    }
+
+    if texture.0 != tile_type.texture {
+        tile.insert(TileTexture(tile_type.texture));
+    }
+
+    let mut changed = false;
+    if walkable.is_some() != tile_type.walkable {
+        if tile_type.walkable {
+            tile.insert(Walkable);
+        } else {
+            tile.remove::<Walkable>();
+        }
+        changed = true;
+    }
+
+    let cost = current_cost.map_or(1.0, |cost| cost.0);
+    if cost != tile_type.cost {
+        if tile_type.cost != 1.0 {
+            tile.insert(TerrainCost(tile_type.cost));
+        } else {
+            tile.remove::<TerrainCost>();
+        }
+        changed = true;
+    }
+
+    if current_staircase.map(|staircase| staircase.to) != staircase.copied() {
+        match staircase {
+            Some(to) => tile.insert(Staircase { to: *to }),
+            None => tile.remove::<Staircase>(),
+        };
+        changed = true;
+    }
+
+    changed
+}
+
+/// The glyphs of a floor, one string per row.
+/// Rows and columns end at the first position without a tile, like the layouts they were spawned from.
+pub fn floor_rows(
+    size: &TilemapSize,
+    storage: &TileStorage,
+    glyph_query: &Query<&Glyph>,
+) -> Vec<String> {
+    let mut rows = Vec::new();
+
+    for y in 0..size.y {
+        let row = (0..size.x)
+            .map_while(|x| {
+                let entity = storage.get(&TilePos { x, y })?;
+                glyph_query.get(entity).ok().map(|glyph| glyph.0)
+            })
+            .collect::<String>();
+
+        if row.is_empty() {
+            break;
+        }
+        rows.push(row);
+    }
+
+    rows
+}
+
+fn switch_active_floor(
+    keyboard: Res<Input<KeyCode>>,
+    mut active_floor: ResMut<ActiveFloor>,
+    map_size: Res<MapSize>,
+) {
+    if keyboard.just_pressed(KeyCode::PageUp) && active_floor.0 + 1 < map_size.floors {
+        active_floor.0 += 1;
+    }
+
+    if keyboard.just_pressed(KeyCode::PageDown) && active_floor.0 > 0 {
+        active_floor.0 -= 1;
+    }
+}
+
+/// Only show the tilemap and the actors of the active floor.
+fn show_active_floor(
+    active_floor: Res<ActiveFloor>,
+    mut visibility_query: Query<(&Floor, &mut Visibility)>,
+) {
+    for (floor, mut visibility) in visibility_query.iter_mut() {
+        visibility.is_visible = floor.0 == active_floor.0;
+    }
+}
+
+/// The tile under a world position, or `None` left of or below the map.
+/// Positions right of or above the map still need to be checked against the `MapSize`.
+pub fn world2d_to_grid(transform: &Vec2) -> Option<UVec2> {
+    let tile = (*transform / TILE_SIZE).floor();
+    if !tile.is_finite() || tile.x < 0.0 || tile.y < 0.0 || tile.max_element() > u32::MAX as f32 {
+        return None;
+    }
+
+    Some(UVec2::new(tile.x as u32, tile.y as u32))
+}
+
+pub fn grid_to_world2d(position: &UVec2) -> Vec2 {
+    // Grid origin is at bottom left
+    let new_x = (position.x as f32 * TILE_SIZE) + (TILE_SIZE / 2.0);
+    let new_y = (position.y as f32 * TILE_SIZE) + (TILE_SIZE / 2.0);
+
+    Vec2::new(new_x, new_y)
+}
@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+
+use crate::components::path_finding::grid::{Floor, Glyph, GridPosition};
+
+use super::asset::MapDefinition;
+use super::{change_tile, staircase_link, MapHandle, MapSize, TileChanged, TileState};
+
+/// Turns tiles into the tile type of a legend glyph.
+/// Tiles changed this way send `TileChanged` like a reloaded map does, so paths and the nav mesh
+/// stay in line with the tiles.
+pub struct PaintTiles(pub Vec<(GridPosition, char)>);
+
+#[allow(clippy::too_many_arguments)]
+pub fn paint_tiles(
+    mut commands: Commands,
+    mut paints: EventReader<PaintTiles>,
+    map_handle: Option<Res<MapHandle>>,
+    maps: Res<Assets<MapDefinition>>,
+    map_size: Res<MapSize>,
+    tilemap_query: Query<(&Floor, &TileStorage)>,
+    tile_query: Query<TileState>,
+    mut tile_changes: EventWriter<TileChanged>,
+) {
+    let map = match map_handle.and_then(|handle| maps.get(&handle.0)) {
+        Some(map) => map,
+        None => return,
+    };
+
+    // Later paints of the same tile win
+    let mut painted = HashMap::new();
+    for PaintTiles(tiles) in paints.iter() {
+        for (position, glyph) in tiles {
+            if !map_size.contains(position) {
+                warn!(
+                    "Cannot paint {} on floor {}, it is outside the map",
+                    position.tile, position.floor
+                );
+            } else if !map.legend.contains_key(glyph) {
+                warn!("Cannot paint {:?}, it is not in the legend", glyph);
+            } else {
+                painted.insert(*position, *glyph);
+            }
+        }
+    }
+
+    if painted.is_empty() {
+        return;
+    }
+
+    let storages = tilemap_query
+        .iter()
+        .map(|(floor, storage)| (floor.0, storage))
+        .collect::<HashMap<_, _>>();
+    let tile_at = |position: &GridPosition| {
+        if !map_size.contains(position) {
+            return None;
+        }
+        storages
+            .get(&position.floor)?
+            .get(&TilePos::from(position.tile))
+    };
+    let glyph_at = |position: &GridPosition| {
+        painted.get(position).copied().or_else(|| {
+            let (glyph, ..) = tile_query.get(tile_at(position)?).ok()?;
+            Some(glyph.0)
+        })
+    };
+
+    // Painting a staircase can link or unlink the tiles above and below it
+    let mut affected = HashSet::default();
+    for position in painted.keys() {
+        affected.insert(*position);
+        affected.insert(GridPosition::new(position.tile, position.floor + 1));
+        if let Some(below) = position.floor.checked_sub(1) {
+            affected.insert(GridPosition::new(position.tile, below));
+        }
+    }
+
+    for position in affected {
+        let entity = match tile_at(&position) {
+            Some(entity) => entity,
+            None => continue,
+        };
+        let tile = match tile_query.get(entity) {
+            Ok(tile) => tile,
+            Err(_) => continue,
+        };
+
+        let glyph = painted.get(&position).copied().unwrap_or(tile.0 .0);
+        let staircase = staircase_link(map, &position, glyph_at);
+        if change_tile(&mut commands, entity, tile, map, glyph, staircase.as_ref()) {
+            tile_changes.send(TileChanged(position));
+        }
+    }
+}
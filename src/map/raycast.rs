@@ -0,0 +1,73 @@
+use bevy::prelude::*;
+
+/// Whether a straight line between the centres of two tiles only crosses clear tiles.
+/// Where the line passes exactly through the corner of four tiles, both tiles beside the corner
+/// have to be clear, so lines never squeeze between two walls that touch diagonally.
+pub fn line_of_sight<F: Fn(UVec2) -> bool>(from: UVec2, to: UVec2, clear: F) -> bool {
+    trace(from, to, &clear, |first, second| {
+        clear(first) && clear(second)
+    })
+}
+
+/// The tiles a straight line between the centres of two tiles crosses, in order.
+/// Consecutive tiles are neighbours, through the corner of four tiles the line steps diagonally.
+pub fn line_tiles(from: UVec2, to: UVec2) -> Vec<UVec2> {
+    let mut tiles = Vec::new();
+    trace(
+        from,
+        to,
+        |tile| {
+            tiles.push(tile);
+            true
+        },
+        |_, _| true,
+    );
+    tiles
+}
+
+/// Walk the tiles a line crosses until `tile` or `corner` returns false.
+/// Returns whether the walk reached `to`.
+fn trace<T, C>(from: UVec2, to: UVec2, mut tile: T, mut corner: C) -> bool
+where
+    T: FnMut(UVec2) -> bool,
+    C: FnMut(UVec2, UVec2) -> bool,
+{
+    let (mut x, mut y) = (from.x as i64, from.y as i64);
+    let (dx, dy) = ((to.x as i64 - x).abs(), (to.y as i64 - y).abs());
+    let (step_x, step_y) = ((to.x as i64 - x).signum(), (to.y as i64 - y).signum());
+    // Tiles between the two ends are never left of or below the map
+    let at = |x: i64, y: i64| UVec2::new(x as u32, y as u32);
+
+    if !tile(at(x, y)) {
+        return false;
+    }
+
+    // Which side of the line the centre of the next tile lies on, doubled to stay in integers
+    let mut error = dx - dy;
+    let mut remaining = dx + dy;
+    while remaining > 0 {
+        if error > 0 {
+            x += step_x;
+            error -= 2 * dy;
+            remaining -= 1;
+        } else if error < 0 {
+            y += step_y;
+            error += 2 * dx;
+            remaining -= 1;
+        } else {
+            if !corner(at(x + step_x, y), at(x, y + step_y)) {
+                return false;
+            }
+            x += step_x;
+            y += step_y;
+            error += 2 * (dx - dy);
+            remaining -= 2;
+        }
+
+        if !tile(at(x, y)) {
+            return false;
+        }
+    }
+
+    true
+}
@@ -0,0 +1,159 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ecs_tilemap::map::TilemapSize;
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+
+use crate::components::path_finding::grid::{Floor, GridPosition};
+
+use super::asset::{FloorLayout, FloorSource, MapDefinition};
+use super::{
+    change_tile, create_floor, link_staircases, spawn_tile, MapSize, TileChanged, TileState,
+    MAP_PATH,
+};
+
+/// Handles to the floor files of the current map.
+#[derive(Default)]
+pub struct FloorSources(Vec<Handle<FloorSource>>);
+
+pub fn watch_floor_sources(
+    mut map_events: EventReader<AssetEvent<MapDefinition>>,
+    maps: Res<Assets<MapDefinition>>,
+    asset_server: Res<AssetServer>,
+    mut sources: ResMut<FloorSources>,
+) {
+    for event in map_events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            if let Some(map) = maps.get(handle) {
+                sources.0 = map
+                    .floors
+                    .iter()
+                    .map(|floor| asset_server.load(floor.path.as_str()))
+                    .collect();
+            }
+        }
+    }
+}
+
+/// The map loader reads the floor files itself, so a changed floor file means the whole map is reloaded.
+pub fn reload_on_floor_change(
+    mut floor_events: EventReader<AssetEvent<FloorSource>>,
+    asset_server: Res<AssetServer>,
+) {
+    let modified = floor_events
+        .iter()
+        .any(|event| matches!(event, AssetEvent::Modified { .. }));
+
+    if modified {
+        asset_server.reload_asset(MAP_PATH);
+    }
+}
+
+/// Apply a reloaded map to the tiles that are already spawned.
+/// Only tiles that differ from the new map are changed, spawned or despawned.
+#[allow(clippy::too_many_arguments)]
+pub fn reload_map(
+    mut commands: Commands,
+    mut map_events: EventReader<AssetEvent<MapDefinition>>,
+    maps: Res<Assets<MapDefinition>>,
+    asset_server: Res<AssetServer>,
+    mut tilemap_query: Query<(Entity, &Floor, &TilemapSize, &mut TileStorage)>,
+    tile_query: Query<TileState>,
+    mut tile_changes: EventWriter<TileChanged>,
+    mut map_size: ResMut<MapSize>,
+) {
+    for event in map_events.iter() {
+        let map = match event {
+            AssetEvent::Modified { handle } => match maps.get(handle) {
+                Some(map) => map,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        info!("Reloading map {}", map.name);
+        *map_size = MapSize::from(map);
+        let staircases = link_staircases(map);
+        let mut existing_floors = 0;
+
+        for (map_entity, floor, size, mut storage) in tilemap_query.iter_mut() {
+            existing_floors += 1;
+            let resized = size.x != map.size.x || size.y != map.size.y;
+            // A resized floor is despawned tile by tile and then spawned again as a whole
+            let layout = map.floors.get(floor.0 as usize).filter(|_| !resized);
+
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let position = GridPosition::new(UVec2::new(x, y), floor.0);
+                    let changed = update_tile(
+                        &mut commands,
+                        map,
+                        layout,
+                        map_entity,
+                        &mut storage,
+                        &tile_query,
+                        &staircases,
+                        position,
+                    );
+
+                    if changed {
+                        tile_changes.send(TileChanged(position));
+                    }
+                }
+            }
+
+            // All tiles of a removed floor are despawned above, only the tilemap itself is left
+            if layout.is_none() {
+                commands.entity(map_entity).despawn_recursive();
+            }
+
+            if resized && (floor.0 as usize) < map.floors.len() {
+                info!("Floor {} was resized to {}", floor.0, map.size);
+                create_floor(&mut commands, &asset_server, map, floor.0, &staircases);
+            } else if layout.is_none() {
+                info!("Floor {} was removed from the map", floor.0);
+            }
+        }
+
+        for floor in existing_floors..map.floors.len() {
+            info!("Floor {} was added to the map", floor);
+            create_floor(&mut commands, &asset_server, map, floor as u32, &staircases);
+        }
+    }
+}
+
+/// Bring a single tile in line with the map, returns whether its walkability or staircase changed.
+#[allow(clippy::too_many_arguments)]
+fn update_tile(
+    commands: &mut Commands,
+    map: &MapDefinition,
+    layout: Option<&FloorLayout>,
+    map_entity: Entity,
+    storage: &mut TileStorage,
+    tile_query: &Query<TileState>,
+    staircases: &HashMap<GridPosition, GridPosition>,
+    position: GridPosition,
+) -> bool {
+    let tile_pos = TilePos::from(position.tile);
+    let glyph = layout.and_then(|layout| layout.glyph(position.tile));
+    let staircase = staircases.get(&position);
+
+    match (storage.get(&tile_pos), glyph) {
+        (None, None) => false,
+        (None, Some(glyph)) => {
+            spawn_tile(commands, map, glyph, position.tile, map_entity, staircase);
+            map.legend[&glyph].walkable
+        }
+        (Some(entity), None) => {
+            let walkable = tile_query
+                .get(entity)
+                .map_or(false, |(_, _, walkable, ..)| walkable.is_some());
+            commands.entity(entity).despawn_recursive();
+            storage.set(&tile_pos, None);
+            walkable
+        }
+        (Some(entity), Some(glyph)) => match tile_query.get(entity) {
+            Ok(tile) => change_tile(commands, entity, tile, map, glyph, staircase),
+            Err(_) => false,
+        },
+    }
+}
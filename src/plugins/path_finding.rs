@@ -1,28 +1,103 @@
-use std::sync::{Arc, Mutex};
-
 use bevy::prelude::*;
 
 use crate::{
-    resources::nav_mesh::NavMesh,
+    components::path_finding::path::PathAlgorithm,
+    events::path_finding::{DestinationReached, PathFailed, PathFound, PathRequested},
+    resources::{
+        flow_field::FlowFields,
+        nav_grid::{CornerCutting, Navigation},
+        path_cache::PathCache,
+        reservation::ReservationTable,
+    },
     systems::path_finding::{
+        chase::chase_targets,
         find::{
-            calculate_paths, handle_completed_path, schedule_new_path_finding, PathFindingRequests,
+            advance_reservations, calculate_paths, cancel_path_finding, handle_completed_path,
+            handle_shared_searches, invalidate_path_cache, replan_affected_paths,
+            schedule_new_path_finding,
         },
+        flow_field::{handle_completed_flow_fields, invalidate_flow_fields, request_flow_fields},
         // mesh::calculate_new_nav_mesh,
-        steering::transform_path,
+        mesh::{detect_walkable_changes, update_nav_grid},
+        queue::{
+            setup_diagnostics, ChaseSettings, CooperativeSearch, HierarchicalSearch,
+            PathCacheSettings, PathFindingRequests, RequestFallbacks, SchedulerSettings,
+        },
+        steering::{arrive_at_destination, climb_stairs, transform_path, PathSmoothing},
     },
 };
 
-pub struct PathFindingPlugin;
+/// Finds paths for actors with a `Destination`.
+#[derive(Default)]
+pub struct PathFindingPlugin {
+    /// Whether diagonal moves may squeeze past walls.
+    pub corner_cutting: CornerCutting,
+    pub scheduler: SchedulerSettings,
+    pub fallbacks: RequestFallbacks,
+    pub hierarchical: HierarchicalSearch,
+    /// The algorithm for actors without a `PathAlgorithm` of their own.
+    pub algorithm: PathAlgorithm,
+    pub smoothing: PathSmoothing,
+    pub cooperative: CooperativeSearch,
+    pub cache: PathCacheSettings,
+    pub chase: ChaseSettings,
+}
 
 impl Plugin for PathFindingPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Arc::<Mutex<NavMesh>>::default())
+        app.insert_resource(Navigation::default())
+            .insert_resource(self.corner_cutting)
+            .insert_resource(self.scheduler)
+            .insert_resource(self.fallbacks)
+            .insert_resource(self.hierarchical)
+            .insert_resource(self.algorithm)
+            .insert_resource(self.smoothing)
+            .insert_resource(self.cooperative)
+            .insert_resource(ReservationTable::default())
+            .insert_resource(self.cache)
+            .insert_resource(PathCache::new(self.cache.capacity))
+            .insert_resource(self.chase)
             .insert_resource(PathFindingRequests::default())
+            .insert_resource(FlowFields::default())
+            .add_event::<PathRequested>()
+            .add_event::<PathFound>()
+            .add_event::<PathFailed>()
+            .add_event::<DestinationReached>()
+            .add_startup_system(setup_diagnostics)
+            .add_system(chase_targets)
             .add_system(schedule_new_path_finding)
-            .add_system(calculate_paths.after(schedule_new_path_finding))
-            .add_system(handle_completed_path)
+            .add_system(
+                calculate_paths
+                    .after(schedule_new_path_finding)
+                    .after(update_nav_grid)
+                    .after(invalidate_path_cache),
+            )
+            .add_system(handle_completed_path.after(schedule_new_path_finding))
+            .add_system(handle_shared_searches.after(schedule_new_path_finding))
+            .add_system(invalidate_path_cache)
+            .add_system_to_stage(CoreStage::PostUpdate, cancel_path_finding)
+            .add_system(invalidate_flow_fields)
+            .add_system(
+                request_flow_fields
+                    .after(invalidate_flow_fields)
+                    .after(update_nav_grid)
+                    .after(calculate_paths),
+            )
+            .add_system(handle_completed_flow_fields.after(invalidate_flow_fields))
+            .add_system(update_nav_grid)
+            .add_system_to_stage(CoreStage::PostUpdate, detect_walkable_changes)
+            .add_system(replan_affected_paths.after(update_nav_grid))
             // .add_system(calculate_new_nav_mesh)
-            .add_system(transform_path);
+            .add_system(transform_path)
+            .add_system(advance_reservations.before(climb_stairs))
+            .add_system(climb_stairs)
+            // Requests leave the queue in `calculate_paths`, actors only wait on `PendingPath`
+            // from the end of the stage
+            .add_system(
+                arrive_at_destination
+                    .after(climb_stairs)
+                    .after(schedule_new_path_finding)
+                    .before(calculate_paths),
+            );
     }
 }
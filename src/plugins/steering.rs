@@ -1,7 +1,9 @@
 use bevy::prelude::*;
 
+use crate::systems::path_finding::chase::chase_targets;
 use crate::systems::steering::{
-    apply, follow_path::follow_path, follow_path::path_culling, seek::seek,
+    apply, follow_flow_field::follow_flow_field, follow_path::follow_path,
+    follow_path::path_culling, pursuit::pursuit, seek::seek,
 };
 
 pub struct SteeringPlugin;
@@ -12,6 +14,9 @@ impl Plugin for SteeringPlugin {
             // .add_system(follow_mouse.before(apply))
             .add_system(follow_path.before(apply))
             .add_system(path_culling.before(apply))
+            .add_system(follow_flow_field.before(apply))
+            // Runs after chases decide whether to pursue, so the hand-over is the same every frame
+            .add_system(pursuit.after(chase_targets).before(apply))
             .add_system(seek.before(apply))
             .add_system(apply);
     }
@@ -0,0 +1,503 @@
+use std::iter;
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use pathfinding::prelude::*;
+
+use crate::components::path_finding::grid::GridPosition;
+use crate::systems::path_finding::STRAIGHT_COST;
+
+use super::nav_grid::{CornerCutting, NavGrid};
+
+/// Tiles per side of a cluster.
+pub const CLUSTER_SIZE: u32 = 16;
+/// Entrances at least this wide get a transition at both ends instead of one in the middle.
+const WIDE_ENTRANCE: u32 = 6;
+
+/// A square of tiles on a single floor, identified by its column and row of clusters.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct ClusterId {
+    x: u32,
+    y: u32,
+    floor: u32,
+}
+
+impl ClusterId {
+    fn of(position: &GridPosition) -> Self {
+        ClusterId {
+            x: position.tile.x / CLUSTER_SIZE,
+            y: position.tile.y / CLUSTER_SIZE,
+            floor: position.floor,
+        }
+    }
+
+    fn contains(&self, position: &GridPosition) -> bool {
+        ClusterId::of(position) == *self
+    }
+
+    fn exists(&self, grid: &NavGrid) -> bool {
+        let corner = GridPosition::new(UVec2::new(self.x, self.y) * CLUSTER_SIZE, self.floor);
+        grid.contains(&corner)
+    }
+
+    /// The clusters to the right of and above this one. Each border is stored with the cluster
+    /// left of or below it.
+    fn following(&self) -> [ClusterId; 2] {
+        [
+            ClusterId {
+                x: self.x + 1,
+                ..*self
+            },
+            ClusterId {
+                y: self.y + 1,
+                ..*self
+            },
+        ]
+    }
+
+    /// The clusters to the left of and below this one.
+    fn preceding(&self) -> impl Iterator<Item = ClusterId> {
+        let left = self.x.checked_sub(1).map(|x| ClusterId { x, ..*self });
+        let below = self.y.checked_sub(1).map(|y| ClusterId { y, ..*self });
+        left.into_iter().chain(below)
+    }
+}
+
+/// Hierarchical abstraction of a `NavGrid` for HPA* searches.
+///
+/// Every floor is cut into clusters of `CLUSTER_SIZE` tiles. Where walkable tiles face each other
+/// across the border of two clusters, transitions connect pairs of them: one in the middle of a
+/// narrow entrance, one at each end of a wide one. Transitions and staircases are the nodes of the
+/// abstract graph, and nodes of the same cluster are connected by the cost of the cheapest path
+/// between them that stays inside the cluster.
+///
+/// A search on the abstract graph is refined into tiles one cluster at a time, so the path is
+/// optimal inside every cluster but can only cross borders at transitions. No transition is more
+/// than `CLUSTER_SIZE / 2` tiles from any tile of its entrance, and a diagonal step across a
+/// border can be replaced by two straight ones. With `CornerCutting::Never` a path therefore costs
+/// at most `CLUSTER_SIZE * STRAIGHT_COST + DIAGONAL_COST` more than the optimal path for each
+/// border the optimal path crosses, scaled by the heaviest weight of the tiles along that border.
+/// Staircases are nodes themselves and add nothing.
+#[derive(Clone, Default, Debug)]
+pub struct AbstractGraph {
+    /// Transitions on the border between a cluster and the cluster right of or above it.
+    borders: HashMap<(ClusterId, ClusterId), Vec<(GridPosition, GridPosition)>>,
+    nodes: HashMap<ClusterId, Vec<GridPosition>>,
+    /// Costs between nodes of the same cluster.
+    edges: HashMap<GridPosition, Vec<(GridPosition, u32)>>,
+}
+
+impl AbstractGraph {
+    /// Rebuild the clusters that contain changed tiles, and the borders and nodes of the clusters
+    /// around them. The rest of the graph is kept as it is.
+    pub fn update(
+        &mut self,
+        grid: &NavGrid,
+        changed: &[GridPosition],
+        corner_cutting: CornerCutting,
+    ) {
+        let dirty = changed
+            .iter()
+            .filter(|position| grid.contains(position))
+            .map(ClusterId::of)
+            .collect::<HashSet<_>>();
+        if dirty.is_empty() {
+            return;
+        }
+
+        let mut affected = dirty.clone();
+        for cluster in &dirty {
+            for next in cluster.following() {
+                if next.exists(grid) {
+                    self.update_border(grid, *cluster, next);
+                    affected.insert(next);
+                }
+            }
+            for previous in cluster.preceding() {
+                self.update_border(grid, previous, *cluster);
+                affected.insert(previous);
+            }
+        }
+
+        // Nodes on the borders of the dirty clusters belong to the clusters around them as well
+        for cluster in affected {
+            self.update_cluster(grid, cluster, corner_cutting);
+        }
+    }
+
+    fn update_border(&mut self, grid: &NavGrid, first: ClusterId, second: ClusterId) {
+        let size = grid.size();
+        // Pairs of tiles facing each other across the border, from one end of it to the other
+        let vertical = second.x > first.x;
+        let (start, length) = if vertical {
+            (
+                first.y * CLUSTER_SIZE,
+                CLUSTER_SIZE.min(size.y - first.y * CLUSTER_SIZE),
+            )
+        } else {
+            (
+                first.x * CLUSTER_SIZE,
+                CLUSTER_SIZE.min(size.x - first.x * CLUSTER_SIZE),
+            )
+        };
+        let pair = |offset: u32| {
+            let along = start + offset;
+            let (inside, outside) = if vertical {
+                let x = second.x * CLUSTER_SIZE;
+                (UVec2::new(x - 1, along), UVec2::new(x, along))
+            } else {
+                let y = second.y * CLUSTER_SIZE;
+                (UVec2::new(along, y - 1), UVec2::new(along, y))
+            };
+            (
+                GridPosition::new(inside, first.floor),
+                GridPosition::new(outside, first.floor),
+            )
+        };
+        let open = |offset: u32| {
+            let (inside, outside) = pair(offset);
+            grid.is_walkable(&inside) && grid.is_walkable(&outside)
+        };
+
+        let mut transitions = Vec::new();
+        let mut offset = 0;
+        while offset < length {
+            if !open(offset) {
+                offset += 1;
+                continue;
+            }
+
+            let entrance_start = offset;
+            while offset < length && open(offset) {
+                offset += 1;
+            }
+            let entrance_end = offset - 1;
+
+            if entrance_end - entrance_start + 1 < WIDE_ENTRANCE {
+                transitions.push(pair((entrance_start + entrance_end) / 2));
+            } else {
+                transitions.push(pair(entrance_start));
+                transitions.push(pair(entrance_end));
+            }
+        }
+
+        self.borders.insert((first, second), transitions);
+    }
+
+    fn update_cluster(
+        &mut self,
+        grid: &NavGrid,
+        cluster: ClusterId,
+        corner_cutting: CornerCutting,
+    ) {
+        for node in self.nodes.remove(&cluster).unwrap_or_default() {
+            self.edges.remove(&node);
+        }
+
+        let mut nodes = self
+            .transitions(cluster)
+            .into_iter()
+            .map(|(node, _)| node)
+            .chain(
+                grid.staircases()
+                    .map(|(from, _)| from)
+                    .filter(|from| cluster.contains(from)),
+            )
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|node| (node.tile.y, node.tile.x));
+        nodes.dedup();
+
+        for node in &nodes {
+            let reachable = dijkstra_all(node, |current| {
+                local_neighbours(grid, cluster, current, corner_cutting)
+            });
+            let edges = nodes
+                .iter()
+                .filter_map(|other| reachable.get(other).map(|(_, cost)| (*other, *cost)))
+                .collect();
+            self.edges.insert(*node, edges);
+        }
+
+        self.nodes.insert(cluster, nodes);
+    }
+
+    /// The transitions of a cluster, as the tile inside the cluster and the tile it leads to.
+    fn transitions(&self, cluster: ClusterId) -> Vec<(GridPosition, GridPosition)> {
+        let mut transitions = Vec::new();
+        for next in cluster.following() {
+            if let Some(border) = self.borders.get(&(cluster, next)) {
+                transitions.extend(border.iter().copied());
+            }
+        }
+        for previous in cluster.preceding() {
+            if let Some(border) = self.borders.get(&(previous, cluster)) {
+                transitions.extend(border.iter().map(|(outside, inside)| (*inside, *outside)));
+            }
+        }
+
+        transitions
+    }
+
+    /// Moves from a node of the abstract graph to other nodes.
+    fn abstract_neighbours(&self, grid: &NavGrid, node: &GridPosition) -> Vec<(GridPosition, u32)> {
+        let mut neighbours = self.edges.get(node).cloned().unwrap_or_default();
+        neighbours.extend(
+            self.transitions(ClusterId::of(node))
+                .into_iter()
+                .filter(|(inside, _)| inside == node)
+                .map(|(_, outside)| (outside, STRAIGHT_COST * grid.weight(&outside) / 100)),
+        );
+        neighbours.extend(
+            grid.neighbours(node, CornerCutting::Never)
+                .into_iter()
+                .filter(|(to, _)| to.floor != node.floor),
+        );
+        neighbours
+    }
+
+    /// Find a path on the abstract graph and refine it into tiles.
+    /// `successors` and `heuristic` are used for the searches on tiles, so tie-breakers apply the
+    /// same way as on a full grid search.
+    pub fn find_path<S, H>(
+        &self,
+        grid: &NavGrid,
+        from: &GridPosition,
+        to: &GridPosition,
+        corner_cutting: CornerCutting,
+        successors: S,
+        heuristic: H,
+    ) -> Option<Vec<GridPosition>>
+    where
+        S: Fn(&GridPosition) -> Vec<(GridPosition, u32)>,
+        H: Fn(&GridPosition, &GridPosition) -> u32,
+    {
+        let start_cluster = ClusterId::of(from);
+        let goal_cluster = ClusterId::of(to);
+        let local = |cluster: ClusterId, current: &GridPosition| {
+            successors(current)
+                .into_iter()
+                .filter(|(next, _)| cluster.contains(next))
+                .collect::<Vec<_>>()
+        };
+
+        // Connect the start and the goal to the nodes of their clusters
+        let from_start = dijkstra_all(from, |current| local(start_cluster, current));
+        let to_goal = dijkstra_all(to, |current| {
+            grid.predecessors(current, corner_cutting)
+                .into_iter()
+                .filter(|(previous, _)| goal_cluster.contains(previous))
+                .collect::<Vec<_>>()
+        });
+        let goal_nodes = self
+            .nodes
+            .get(&goal_cluster)
+            .into_iter()
+            .flatten()
+            .filter_map(|node| to_goal.get(node).map(|(_, cost)| (*node, *cost)))
+            .collect::<HashMap<_, _>>();
+
+        let (abstract_path, _) = astar(
+            from,
+            |node| {
+                let mut neighbours = self.abstract_neighbours(grid, node);
+                if node == from {
+                    let reachable = self
+                        .nodes
+                        .get(&start_cluster)
+                        .into_iter()
+                        .flatten()
+                        .chain(iter::once(to))
+                        .filter_map(|other| from_start.get(other).map(|(_, cost)| (*other, *cost)));
+                    neighbours.extend(reachable);
+                }
+                if let Some(cost) = goal_nodes.get(node) {
+                    neighbours.push((*to, *cost));
+                }
+                neighbours
+            },
+            |node| heuristic(node, to),
+            |node| node == to,
+        )?;
+
+        let mut path = vec![*from];
+        for step in abstract_path.windows(2) {
+            let (start, end) = (step[0], step[1]);
+            let cluster = ClusterId::of(&start);
+            if !cluster.contains(&end) {
+                // Transitions and staircases are a single move
+                path.push(end);
+                continue;
+            }
+
+            let (tiles, _) = astar(
+                &start,
+                |current| local(cluster, current),
+                |current| heuristic(current, &end),
+                |current| *current == end,
+            )?;
+            path.extend(tiles.into_iter().skip(1));
+        }
+
+        Some(path)
+    }
+}
+
+/// Moves from `current` that stay inside `cluster`.
+fn local_neighbours(
+    grid: &NavGrid,
+    cluster: ClusterId,
+    current: &GridPosition,
+    corner_cutting: CornerCutting,
+) -> Vec<(GridPosition, u32)> {
+    grid.neighbours(current, corner_cutting)
+        .into_iter()
+        .filter(|(next, _)| cluster.contains(next))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::systems::path_finding::finder::heuristic;
+    use crate::systems::path_finding::DIAGONAL_COST;
+
+    use super::*;
+
+    const SIZE: u32 = 3 * CLUSTER_SIZE;
+
+    /// A single floor grid of three by three clusters with a fifth of the tiles walls and some
+    /// tiles cheaper or more expensive to cross.
+    fn random_grid(rng: &mut StdRng) -> NavGrid {
+        let mut grid = NavGrid::new(UVec2::splat(SIZE), 1);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let position = GridPosition::new(UVec2::new(x, y), 0);
+                grid.set_walkable(&position, rng.gen_bool(0.8));
+                if rng.gen_bool(0.1) {
+                    grid.set_weight(&position, [0.5, 2.0, 3.0][rng.gen_range(0..3)]);
+                }
+            }
+        }
+        grid
+    }
+
+    fn random_walkable(rng: &mut StdRng, grid: &NavGrid) -> GridPosition {
+        loop {
+            let tile = UVec2::new(rng.gen_range(0..SIZE), rng.gen_range(0..SIZE));
+            let position = GridPosition::new(tile, 0);
+            if grid.is_walkable(&position) {
+                return position;
+            }
+        }
+    }
+
+    /// The heaviest weight of the two rows of tiles along the border crossed between `from` and
+    /// `to` on one axis, over the clusters both tiles are in on the other axis.
+    fn border_weight(grid: &NavGrid, from: u32, to: u32, across: [u32; 2], vertical: bool) -> u32 {
+        let border = from.max(to);
+        let first = across[0].min(across[1]) / CLUSTER_SIZE * CLUSTER_SIZE;
+        let last = (across[0].max(across[1]) / CLUSTER_SIZE + 1) * CLUSTER_SIZE;
+        (first..last.min(SIZE))
+            .flat_map(|along| [(border - 1, along), (border, along)])
+            .map(|(a, b)| if vertical { (a, b) } else { (b, a) })
+            .map(|(x, y)| grid.weight(&GridPosition::new(UVec2::new(x, y), 0)))
+            .max()
+            .unwrap_or(100)
+    }
+
+    /// Extra cost the documented bound allows for the borders `path` crosses.
+    fn allowed_detour(grid: &NavGrid, path: &[GridPosition]) -> u32 {
+        let per_border = CLUSTER_SIZE * STRAIGHT_COST + DIAGONAL_COST;
+        path.windows(2)
+            .map(|step| {
+                let (from, to) = (step[0].tile, step[1].tile);
+                let mut extra = 0;
+                if from.x / CLUSTER_SIZE != to.x / CLUSTER_SIZE {
+                    let weight = border_weight(grid, from.x, to.x, [from.y, to.y], true);
+                    extra += per_border * weight / 100;
+                }
+                if from.y / CLUSTER_SIZE != to.y / CLUSTER_SIZE {
+                    let weight = border_weight(grid, from.y, to.y, [from.x, to.x], false);
+                    extra += per_border * weight / 100;
+                }
+                extra
+            })
+            .sum()
+    }
+
+    fn path_cost(grid: &NavGrid, path: &[GridPosition]) -> u32 {
+        path.windows(2)
+            .map(|step| {
+                grid.neighbours(&step[0], CornerCutting::Never)
+                    .into_iter()
+                    .find(|(next, _)| *next == step[1])
+                    .map(|(_, cost)| cost)
+                    .expect("steps of a path are moves on the grid")
+            })
+            .sum()
+    }
+
+    #[test]
+    fn cost_within_bound_of_a_star_on_random_grids() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let mut found = 0;
+
+        for _ in 0..20 {
+            let grid = random_grid(&mut rng);
+            let tiles = (0..SIZE)
+                .flat_map(|y| (0..SIZE).map(move |x| GridPosition::new(UVec2::new(x, y), 0)))
+                .collect::<Vec<_>>();
+            let mut graph = AbstractGraph::default();
+            graph.update(&grid, &tiles, CornerCutting::Never);
+
+            let cheapest = grid.cheapest_weight();
+            let estimate =
+                |from: &GridPosition, to: &GridPosition| heuristic(from, to) * cheapest / 100;
+            for _ in 0..10 {
+                let from = random_walkable(&mut rng, &grid);
+                let to = random_walkable(&mut rng, &grid);
+                let expected = astar(
+                    &from,
+                    |node| grid.neighbours(node, CornerCutting::Never),
+                    |node| estimate(node, &to),
+                    |node| *node == to,
+                );
+                let hierarchical = graph.find_path(
+                    &grid,
+                    &from,
+                    &to,
+                    CornerCutting::Never,
+                    |node| grid.neighbours(node, CornerCutting::Never),
+                    estimate,
+                );
+
+                let (optimal, expected) = match expected {
+                    Some(expected) => expected,
+                    None => {
+                        assert!(hierarchical.is_none(), "from {:?} to {:?}", from, to);
+                        continue;
+                    }
+                };
+                let path = hierarchical.expect("A* found a path, so HPA* has to find one");
+                assert_eq!(path.first(), Some(&from));
+                assert_eq!(path.last(), Some(&to));
+                let cost = path_cost(&grid, &path);
+                let bound = expected + allowed_detour(&grid, &optimal);
+                assert!(
+                    expected <= cost && cost <= bound,
+                    "from {:?} to {:?}: A* {}, HPA* {}, bound {}",
+                    from,
+                    to,
+                    expected,
+                    cost,
+                    bound
+                );
+                found += 1;
+            }
+        }
+
+        assert!(found > 0);
+    }
+}
@@ -0,0 +1,105 @@
+use bevy::tasks::Task;
+use bevy::utils::{HashMap, HashSet};
+use pathfinding::prelude::*;
+
+use crate::components::path_finding::grid::GridPosition;
+
+use super::nav_grid::{CornerCutting, NavGrid};
+
+/// The cheapest way to a single destination from every tile that can reach it.
+///
+/// A single Dijkstra search from the destination gives both the integration field, the cost of
+/// getting to the destination, and the direction field, the next tile on the way there.
+/// The search follows moves backwards, so it pays for each tile a move enters.
+pub struct FlowField {
+    destination: GridPosition,
+    /// Next tile towards the destination and the cost from here, for every tile but the destination.
+    field: HashMap<GridPosition, (GridPosition, u32)>,
+}
+
+impl FlowField {
+    pub fn new(grid: &NavGrid, destination: GridPosition, corner_cutting: CornerCutting) -> Self {
+        let field = dijkstra_all(&destination, |node| grid.predecessors(node, corner_cutting));
+        FlowField {
+            destination,
+            field: field.into_iter().collect(),
+        }
+    }
+
+    pub fn destination(&self) -> GridPosition {
+        self.destination
+    }
+
+    /// Cost of the cheapest path from `position` to the destination.
+    pub fn cost(&self, position: &GridPosition) -> Option<u32> {
+        if *position == self.destination {
+            return Some(0);
+        }
+        self.field.get(position).map(|(_, cost)| *cost)
+    }
+
+    /// The tile to move to from `position`, `None` on the destination and on tiles that cannot
+    /// reach it.
+    pub fn next(&self, position: &GridPosition) -> Option<GridPosition> {
+        self.field.get(position).map(|(next, _)| *next)
+    }
+
+    /// The tiles from `from` to the destination.
+    pub fn path(&self, from: &GridPosition) -> Option<Vec<GridPosition>> {
+        self.cost(from)?;
+
+        let mut path = vec![*from];
+        let mut current = *from;
+        while let Some(next) = self.next(&current) {
+            path.push(next);
+            current = next;
+        }
+        Some(path)
+    }
+}
+
+/// Flow fields of the destinations actors are following, and the fields still being computed.
+/// Tile changes invalidate every field, they are computed again for the actors still following them.
+#[derive(Default)]
+pub struct FlowFields {
+    fields: HashMap<GridPosition, FlowField>,
+    pending: HashMap<GridPosition, Task<FlowField>>,
+}
+
+impl FlowFields {
+    pub fn get(&self, destination: &GridPosition) -> Option<&FlowField> {
+        self.fields.get(destination)
+    }
+
+    /// Whether a field for `destination` is ready or being computed.
+    pub fn contains(&self, destination: &GridPosition) -> bool {
+        self.fields.contains_key(destination) || self.pending.contains_key(destination)
+    }
+
+    pub fn start(&mut self, destination: GridPosition, task: Task<FlowField>) {
+        self.pending.insert(destination, task);
+    }
+
+    pub fn pending_mut(&mut self) -> impl Iterator<Item = (&GridPosition, &mut Task<FlowField>)> {
+        self.pending.iter_mut()
+    }
+
+    /// Move a computed field out of the pending ones.
+    pub fn complete(&mut self, field: FlowField) {
+        self.pending.remove(&field.destination);
+        self.fields.insert(field.destination, field);
+    }
+
+    /// Forget the fields, and cancel the computations, of destinations no one follows any more.
+    pub fn retain(&mut self, destinations: &HashSet<GridPosition>) {
+        self.fields
+            .retain(|destination, _| destinations.contains(destination));
+        self.pending
+            .retain(|destination, _| destinations.contains(destination));
+    }
+
+    pub fn clear(&mut self) {
+        self.fields.clear();
+        self.pending.clear();
+    }
+}
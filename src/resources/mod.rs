@@ -1 +1,5 @@
-pub mod nav_mesh;
+pub mod abstract_graph;
+pub mod flow_field;
+pub mod nav_grid;
+pub mod path_cache;
+pub mod reservation;
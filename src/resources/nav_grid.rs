@@ -0,0 +1,499 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::components::path_finding::grid::GridPosition;
+use crate::map::raycast::line_of_sight;
+use crate::resources::abstract_graph::AbstractGraph;
+use crate::systems::path_finding::{DIAGONAL_COST, STAIRS_COST, STRAIGHT_COST};
+
+const BITS: usize = u64::BITS as usize;
+
+/// When a diagonal move may pass the corner between the two tiles it cuts past.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CornerCutting {
+    /// Both tiles next to the diagonal have to be walkable.
+    Never,
+    /// At least one of the tiles next to the diagonal has to be walkable.
+    OneSideFree,
+    /// Only the diagonal tile itself has to be walkable.
+    Always,
+}
+
+impl Default for CornerCutting {
+    fn default() -> Self {
+        CornerCutting::Never
+    }
+}
+
+impl CornerCutting {
+    fn allows(self, horizontal_free: bool, vertical_free: bool) -> bool {
+        match self {
+            CornerCutting::Never => horizontal_free && vertical_free,
+            CornerCutting::OneSideFree => horizontal_free || vertical_free,
+            CornerCutting::Always => true,
+        }
+    }
+}
+
+/// The eight tiles around a tile, straight moves first.
+pub const OFFSETS: [(i64, i64); 8] = [
+    (0, 1),
+    (0, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (1, 1),
+    (-1, -1),
+    (1, -1),
+];
+
+/// Which tiles can be walked on and where the staircases lead, for every floor of the map.
+///
+/// Walkability is a bitset indexed by floor, row and column, so looking up a tile is a shift and
+/// a mask. Searches only ever read the grid, tile changes are applied to a copy.
+///
+/// Walkable tiles are also labelled with the region they belong to, tiles in the same region
+/// can reach each other. Regions can span several floors through staircases.
+#[derive(Clone, Default, Debug)]
+pub struct NavGrid {
+    size: UVec2,
+    floors: u32,
+    walkable: Vec<u64>,
+    staircases: HashMap<GridPosition, GridPosition>,
+    /// Cost multiplier of moving onto every tile, in percent.
+    weights: Vec<u16>,
+    /// Number of tiles whose weight is not 100%.
+    weighted: usize,
+    /// The lowest weight a tile ever had, a lower bound for the weight of any move.
+    cheapest: u16,
+    /// Region of every tile, 0 for tiles that are not walkable.
+    regions: Vec<u32>,
+    /// Tiles of every region, to relabel a region without visiting the whole grid.
+    region_tiles: HashMap<u32, Vec<usize>>,
+    next_region: u32,
+}
+
+impl NavGrid {
+    /// A grid of the given size without any walkable tiles.
+    pub fn new(size: UVec2, floors: u32) -> Self {
+        let tiles = size.x as usize * size.y as usize * floors as usize;
+        NavGrid {
+            size,
+            floors,
+            walkable: vec![0; (tiles + BITS - 1) / BITS],
+            staircases: HashMap::default(),
+            weights: vec![100; tiles],
+            weighted: 0,
+            cheapest: 100,
+            regions: vec![0; tiles],
+            region_tiles: HashMap::default(),
+            next_region: 1,
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    pub fn floors(&self) -> u32 {
+        self.floors
+    }
+
+    pub fn contains(&self, position: &GridPosition) -> bool {
+        position.floor < self.floors && position.tile.cmplt(self.size).all()
+    }
+
+    fn index(&self, position: &GridPosition) -> Option<usize> {
+        if !self.contains(position) {
+            return None;
+        }
+
+        let floor = position.floor as usize * self.size.x as usize * self.size.y as usize;
+        Some(floor + position.tile.y as usize * self.size.x as usize + position.tile.x as usize)
+    }
+
+    /// Whether the tile exists and can be walked on.
+    pub fn is_walkable(&self, position: &GridPosition) -> bool {
+        self.index(position).map_or(false, |index| {
+            self.walkable[index / BITS] & (1 << (index % BITS)) != 0
+        })
+    }
+
+    /// The walkable tile closest to `position` on the same floor, at most `radius` tiles away
+    /// horizontally and vertically.
+    pub fn nearest_walkable(&self, position: &GridPosition, radius: u32) -> Option<GridPosition> {
+        if self.is_walkable(position) {
+            return Some(*position);
+        }
+
+        let radius = radius as i64;
+        let x = position.tile.x as i64;
+        let y = position.tile.y as i64;
+        (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter_map(|(dx, dy)| {
+                let tile = UVec2::new(u32::try_from(x + dx).ok()?, u32::try_from(y + dy).ok()?);
+                let candidate = GridPosition::new(tile, position.floor);
+                self.is_walkable(&candidate)
+                    .then(|| (dx * dx + dy * dy, candidate))
+            })
+            // Equally close tiles are picked by position, so every actor snaps the same way
+            .min_by_key(|(distance, candidate)| (*distance, candidate.tile.y, candidate.tile.x))
+            .map(|(_, candidate)| candidate)
+    }
+
+    pub fn set_walkable(&mut self, position: &GridPosition, walkable: bool) {
+        if let Some(index) = self.index(position) {
+            let mask = 1 << (index % BITS);
+            if walkable {
+                self.walkable[index / BITS] |= mask;
+            } else {
+                self.walkable[index / BITS] &= !mask;
+            }
+        }
+    }
+
+    /// Cost multiplier of moving onto `position`, in percent.
+    pub fn weight(&self, position: &GridPosition) -> u32 {
+        self.index(position)
+            .map_or(100, |index| self.weights[index] as u32)
+    }
+
+    /// Set the cost multiplier of moving onto `position`, from a legend cost.
+    pub fn set_weight(&mut self, position: &GridPosition, multiplier: f32) {
+        let index = match self.index(position) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let weight = (multiplier * 100.0).round().clamp(1.0, u16::MAX as f32) as u16;
+        let previous = std::mem::replace(&mut self.weights[index], weight);
+        if previous != 100 {
+            self.weighted -= 1;
+        }
+        if weight != 100 {
+            self.weighted += 1;
+        }
+        self.cheapest = self.cheapest.min(weight);
+    }
+
+    /// Whether every tile costs the same to move onto.
+    pub fn is_uniform(&self) -> bool {
+        self.weighted == 0
+    }
+
+    /// A weight no move is cheaper than. Scaling a distance by it keeps heuristics admissible.
+    pub fn cheapest_weight(&self) -> u32 {
+        self.cheapest as u32
+    }
+
+    /// Whether a straight line between two tiles on the same floor only crosses walkable tiles.
+    pub fn line_of_sight(&self, from: &GridPosition, to: &GridPosition) -> bool {
+        from.floor == to.floor
+            && line_of_sight(from.tile, to.tile, |tile| {
+                self.is_walkable(&GridPosition::new(tile, from.floor))
+            })
+    }
+
+    /// The tile the staircase at `position` leads to, if there is one.
+    pub fn staircase(&self, position: &GridPosition) -> Option<GridPosition> {
+        self.staircases.get(position).copied()
+    }
+
+    /// Every staircase, with the tile it leads to.
+    pub fn staircases(&self) -> impl Iterator<Item = (GridPosition, GridPosition)> + '_ {
+        self.staircases.iter().map(|(from, to)| (*from, *to))
+    }
+
+    pub fn set_staircase(&mut self, position: GridPosition, to: Option<GridPosition>) {
+        match to {
+            Some(to) => self.staircases.insert(position, to),
+            None => self.staircases.remove(&position),
+        };
+    }
+
+    /// The region of a walkable tile.
+    pub fn region(&self, position: &GridPosition) -> Option<u32> {
+        let region = self.regions[self.index(position)?];
+        (region != 0).then(|| region)
+    }
+
+    /// Whether a path from `from` to `to` exists, without searching for it.
+    pub fn connected(&self, from: &GridPosition, to: &GridPosition) -> bool {
+        self.region(from)
+            .map_or(false, |region| self.region(to) == Some(region))
+    }
+
+    /// Label the regions again after the walkability or staircases of `changed` tiles changed.
+    /// Only the regions next to the changed tiles are flooded again, regions they split into or
+    /// merged with get new labels.
+    pub fn update_regions(&mut self, changed: &[GridPosition], corner_cutting: CornerCutting) {
+        let mut seeds = Vec::new();
+        for position in changed {
+            seeds.push(*position);
+            // Neighbours that are not walkable have no region, out of bounds tiles are skipped below
+            for (dx, dy) in OFFSETS {
+                let x = u32::try_from(position.tile.x as i64 + dx);
+                let y = u32::try_from(position.tile.y as i64 + dy);
+                if let (Ok(x), Ok(y)) = (x, y) {
+                    seeds.push(GridPosition::new(UVec2::new(x, y), position.floor));
+                }
+            }
+            seeds.push(GridPosition::new(position.tile, position.floor + 1));
+            if let Some(below) = position.floor.checked_sub(1) {
+                seeds.push(GridPosition::new(position.tile, below));
+            }
+        }
+
+        // Tiles of a region next to a change may now be cut off from the rest of it
+        let dirty = seeds
+            .iter()
+            .filter_map(|seed| self.index(seed))
+            .map(|index| self.regions[index])
+            .filter(|region| *region != 0)
+            .collect::<HashSet<u32>>();
+        let mut unlabelled = seeds
+            .iter()
+            .filter_map(|seed| self.index(seed))
+            .collect::<Vec<usize>>();
+        for region in dirty {
+            for index in self.region_tiles.remove(&region).unwrap_or_default() {
+                self.regions[index] = 0;
+                unlabelled.push(index);
+            }
+        }
+
+        for index in unlabelled {
+            let start = self.position(index);
+            if self.regions[index] != 0 || !self.is_walkable(&start) {
+                continue;
+            }
+
+            let region = self.next_region;
+            self.next_region += 1;
+            self.regions[index] = region;
+            let mut tiles = vec![index];
+
+            let mut open = vec![start];
+            while let Some(current) = open.pop() {
+                for (neighbour, _) in self.neighbours(&current, corner_cutting) {
+                    let index = match self.index(&neighbour) {
+                        Some(index) if self.regions[index] == 0 && self.is_walkable(&neighbour) => {
+                            index
+                        }
+                        _ => continue,
+                    };
+                    self.regions[index] = region;
+                    tiles.push(index);
+                    open.push(neighbour);
+                }
+            }
+            self.region_tiles.insert(region, tiles);
+        }
+    }
+
+    fn position(&self, index: usize) -> GridPosition {
+        let tiles_per_floor = self.size.x as usize * self.size.y as usize;
+        let floor = index / tiles_per_floor;
+        let tile = index % tiles_per_floor;
+        let x = tile % self.size.x as usize;
+        let y = tile / self.size.x as usize;
+
+        GridPosition::new(UVec2::new(x as u32, y as u32), floor as u32)
+    }
+
+    /// The walkable tiles an actor on `current` can move to, with the cost of each move.
+    /// Nothing can be reached from a tile that is not walkable itself.
+    pub fn neighbours(
+        &self,
+        current: &GridPosition,
+        corner_cutting: CornerCutting,
+    ) -> Vec<(GridPosition, u32)> {
+        let mut neighbours = self.moves(current, corner_cutting);
+        for (neighbour, cost) in &mut neighbours {
+            *cost = *cost * self.weight(neighbour) / 100;
+        }
+        neighbours
+    }
+
+    /// The walkable tiles an actor can move to `current` from, with the cost of each move.
+    /// Moves are allowed both ways, only their cost depends on the direction.
+    pub fn predecessors(
+        &self,
+        current: &GridPosition,
+        corner_cutting: CornerCutting,
+    ) -> Vec<(GridPosition, u32)> {
+        let weight = self.weight(current);
+        let mut predecessors = self.moves(current, corner_cutting);
+        for (_, cost) in &mut predecessors {
+            *cost = *cost * weight / 100;
+        }
+        predecessors
+    }
+
+    /// Moves from `current` with the cost of each move on tiles of 100% weight.
+    fn moves(
+        &self,
+        current: &GridPosition,
+        corner_cutting: CornerCutting,
+    ) -> Vec<(GridPosition, u32)> {
+        let mut neighbours = Vec::with_capacity(9);
+        if !self.is_walkable(current) {
+            return neighbours;
+        }
+
+        let offset = |dx: i64, dy: i64| {
+            let x = u32::try_from(current.tile.x as i64 + dx).ok()?;
+            let y = u32::try_from(current.tile.y as i64 + dy).ok()?;
+            Some(GridPosition::new(UVec2::new(x, y), current.floor))
+        };
+        let walkable = |dx, dy| offset(dx, dy).map_or(false, |tile| self.is_walkable(&tile));
+
+        for (dx, dy) in OFFSETS {
+            let neighbour = match offset(dx, dy) {
+                Some(neighbour) if self.is_walkable(&neighbour) => neighbour,
+                _ => continue,
+            };
+
+            let cost = if dx == 0 || dy == 0 {
+                STRAIGHT_COST
+            } else if corner_cutting.allows(walkable(dx, 0), walkable(0, dy)) {
+                DIAGONAL_COST
+            } else {
+                continue;
+            };
+            neighbours.push((neighbour, cost));
+        }
+
+        if let Some(to) = self.staircase(current) {
+            neighbours.push((to, STAIRS_COST));
+        }
+
+        neighbours
+    }
+}
+
+/// The current navigation grid and its abstract graph.
+/// Path finding tasks keep their own `Arc`s to the grid and graph they started with, so they never
+/// wait on tile changes and each is only copied when it changes while a search still uses it.
+#[derive(Clone, Default)]
+pub struct Navigation {
+    pub grid: Arc<NavGrid>,
+    pub graph: Arc<AbstractGraph>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single floor grid, `#` is a wall and any other character is walkable.
+    fn grid(rows: &[&str]) -> NavGrid {
+        let size = UVec2::new(rows[0].len() as u32, rows.len() as u32);
+        let mut grid = NavGrid::new(size, 1);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, glyph) in row.chars().enumerate() {
+                let position = GridPosition::new(UVec2::new(x as u32, y as u32), 0);
+                grid.set_walkable(&position, glyph != '#');
+            }
+        }
+        grid
+    }
+
+    fn can_move(grid: &NavGrid, from: (u32, u32), to: (u32, u32), rule: CornerCutting) -> bool {
+        let from = GridPosition::new(UVec2::new(from.0, from.1), 0);
+        let to = GridPosition::new(UVec2::new(to.0, to.1), 0);
+        grid.neighbours(&from, rule)
+            .iter()
+            .any(|(neighbour, _)| *neighbour == to)
+    }
+
+    #[test]
+    fn diagonal_between_free_tiles() {
+        let grid = grid(&["..", ".."]);
+
+        assert!(can_move(&grid, (0, 0), (1, 1), CornerCutting::Never));
+        assert!(can_move(&grid, (0, 0), (1, 1), CornerCutting::OneSideFree));
+        assert!(can_move(&grid, (0, 0), (1, 1), CornerCutting::Always));
+    }
+
+    #[test]
+    fn diagonal_past_one_wall() {
+        let grid = grid(&["..", "#."]);
+
+        assert!(!can_move(&grid, (0, 0), (1, 1), CornerCutting::Never));
+        assert!(can_move(&grid, (0, 0), (1, 1), CornerCutting::OneSideFree));
+        assert!(can_move(&grid, (0, 0), (1, 1), CornerCutting::Always));
+        // The same corner from the other side
+        assert!(!can_move(&grid, (1, 1), (0, 0), CornerCutting::Never));
+        assert!(can_move(&grid, (1, 1), (0, 0), CornerCutting::OneSideFree));
+    }
+
+    #[test]
+    fn diagonal_between_two_walls() {
+        let grid = grid(&[".#", "#."]);
+
+        assert!(!can_move(&grid, (0, 0), (1, 1), CornerCutting::Never));
+        assert!(!can_move(&grid, (0, 0), (1, 1), CornerCutting::OneSideFree));
+        assert!(can_move(&grid, (0, 0), (1, 1), CornerCutting::Always));
+    }
+
+    #[test]
+    fn diagonal_costs_more_than_straight() {
+        let grid = grid(&["...", "...", "..."]);
+        let centre = GridPosition::new(UVec2::new(1, 1), 0);
+        let neighbours = grid.neighbours(&centre, CornerCutting::Never);
+
+        assert_eq!(neighbours.len(), 8);
+        for (neighbour, cost) in neighbours {
+            let straight = neighbour.tile.x == 1 || neighbour.tile.y == 1;
+            assert_eq!(
+                cost,
+                if straight {
+                    STRAIGHT_COST
+                } else {
+                    DIAGONAL_COST
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn no_moves_from_a_wall() {
+        let grid = grid(&["..", ".#"]);
+
+        assert!(!can_move(&grid, (1, 1), (0, 0), CornerCutting::Always));
+        assert!(!can_move(&grid, (1, 1), (1, 0), CornerCutting::Always));
+    }
+
+    #[test]
+    fn walls_split_and_join_regions() {
+        let mut grid = grid(&["...", "...", "..."]);
+        let all = (0..9)
+            .map(|index| grid.position(index))
+            .collect::<Vec<GridPosition>>();
+        grid.update_regions(&all, CornerCutting::Never);
+        let left = GridPosition::new(UVec2::new(0, 1), 0);
+        let right = GridPosition::new(UVec2::new(2, 1), 0);
+        assert!(grid.connected(&left, &right));
+
+        let wall = (0..3)
+            .map(|y| GridPosition::new(UVec2::new(1, y), 0))
+            .collect::<Vec<GridPosition>>();
+        for position in &wall {
+            grid.set_walkable(position, false);
+        }
+        grid.update_regions(&wall, CornerCutting::Never);
+        assert!(!grid.connected(&left, &right));
+        assert_eq!(grid.region(&wall[1]), None);
+        // Every labelled tile is listed under its region
+        assert_eq!(grid.region_tiles.values().map(Vec::len).sum::<usize>(), 6);
+
+        grid.set_walkable(&wall[2], true);
+        grid.update_regions(&wall[2..], CornerCutting::Never);
+        assert!(grid.connected(&left, &right));
+        assert_eq!(grid.region_tiles.len(), 1);
+        assert_eq!(grid.region_tiles.values().map(Vec::len).sum::<usize>(), 7);
+    }
+}
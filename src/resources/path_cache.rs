@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::components::path_finding::grid::GridPosition;
+use crate::components::path_finding::path::{PathAlgorithm, SearchTask};
+
+use super::nav_grid::CornerCutting;
+
+/// Everything besides its ends that decides which path a search finds.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MovementProfile {
+    pub algorithm: PathAlgorithm,
+    pub corner_cutting: CornerCutting,
+    pub closest_reachable: bool,
+    /// `Dna` of the actor, `None` when actors share paths whatever their tie-breaker.
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PathKey {
+    pub from: GridPosition,
+    pub to: GridPosition,
+    pub profile: MovementProfile,
+}
+
+/// A running search, with the generation of the grid it started on.
+/// Identical requests after a tile change start a search of their own.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SearchId {
+    key: PathKey,
+    generation: u64,
+}
+
+/// A search that every actor with the same request waits for.
+struct SharedSearch {
+    task: SearchTask,
+    /// Actors waiting for the path, with the ID of the request they wait with.
+    waiting: Vec<(Entity, u64)>,
+}
+
+/// Recently found paths, and the searches still running for them, so identical requests share
+/// a single search. Once there are more than `capacity` paths, the least recently used is dropped.
+/// Tile changes invalidate every path, searches that started before them are neither joined nor
+/// cached.
+#[derive(Default)]
+pub struct PathCache {
+    capacity: usize,
+    /// Each path with the time it was last used.
+    paths: HashMap<PathKey, (Vec<GridPosition>, u64)>,
+    recently_used: BTreeMap<u64, PathKey>,
+    clock: u64,
+    searches: HashMap<SearchId, SharedSearch>,
+    generation: u64,
+}
+
+impl PathCache {
+    pub fn new(capacity: usize) -> Self {
+        PathCache {
+            capacity,
+            ..default()
+        }
+    }
+
+    /// The cached path for `key`, which becomes the most recently used.
+    pub fn get(&mut self, key: &PathKey) -> Option<Vec<GridPosition>> {
+        let (path, used) = self.paths.get_mut(key)?;
+        self.recently_used.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.recently_used.insert(self.clock, *key);
+
+        Some(path.clone())
+    }
+
+    /// Let `entity` wait for the running search for `key`, false if there is none that started
+    /// after the last tile change.
+    pub fn join(&mut self, key: &PathKey, entity: Entity, request: u64) -> bool {
+        match self.searches.get_mut(&self.search_id(*key)) {
+            Some(search) => {
+                search.waiting.push((entity, request));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run a search that later actors with the same request can join.
+    pub fn start(&mut self, key: PathKey, task: SearchTask, entity: Entity, request: u64) {
+        self.searches.insert(
+            self.search_id(key),
+            SharedSearch {
+                task,
+                waiting: vec![(entity, request)],
+            },
+        );
+    }
+
+    pub fn searching(&self) -> usize {
+        self.searches.len()
+    }
+
+    pub fn searches_mut(&mut self) -> impl Iterator<Item = (&SearchId, &mut SearchTask)> {
+        self.searches
+            .iter_mut()
+            .map(|(id, search)| (id, &mut search.task))
+    }
+
+    /// Finish a search and cache the path it found, unless tiles changed since it started.
+    /// Returns the actors that waited for it.
+    pub fn complete(&mut self, id: &SearchId, path: Option<&[GridPosition]>) -> Vec<(Entity, u64)> {
+        let search = match self.searches.remove(id) {
+            Some(search) => search,
+            None => return Vec::new(),
+        };
+
+        if let Some(path) = path {
+            if id.generation == self.generation {
+                self.insert(id.key, path.to_vec());
+            }
+        }
+
+        search.waiting
+    }
+
+    /// Forget every path, the tiles they were found on changed.
+    pub fn invalidate(&mut self) {
+        self.paths.clear();
+        self.recently_used.clear();
+        self.generation += 1;
+    }
+
+    fn search_id(&self, key: PathKey) -> SearchId {
+        SearchId {
+            key,
+            generation: self.generation,
+        }
+    }
+
+    fn insert(&mut self, key: PathKey, path: Vec<GridPosition>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.clock += 1;
+        if let Some((_, used)) = self.paths.insert(key, (path, self.clock)) {
+            self.recently_used.remove(&used);
+        }
+        self.recently_used.insert(self.clock, key);
+
+        while self.paths.len() > self.capacity {
+            let oldest = match self.recently_used.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            if let Some(key) = self.recently_used.remove(&oldest) {
+                self.paths.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::TaskPool;
+
+    use crate::events::path_finding::PathFailure;
+    use crate::systems::path_finding::finder::SearchStats;
+
+    use super::*;
+
+    fn key(x: u32) -> PathKey {
+        PathKey {
+            from: GridPosition::new(UVec2::new(x, 0), 0),
+            to: GridPosition::new(UVec2::new(x, 5), 0),
+            profile: MovementProfile {
+                algorithm: PathAlgorithm::AStar,
+                corner_cutting: CornerCutting::Never,
+                closest_reachable: false,
+                seed: None,
+            },
+        }
+    }
+
+    /// A search whose result is never looked at, the cache only hands tasks around.
+    fn task() -> SearchTask {
+        TaskPool::new()
+            .spawn(async { (Err(PathFailure::Unreachable), SearchStats::default(), None) })
+    }
+
+    fn search_id(cache: &mut PathCache, key: &PathKey) -> SearchId {
+        *cache
+            .searches_mut()
+            .map(|(id, _)| id)
+            .find(|id| id.key == *key)
+            .expect("a search for the key is running")
+    }
+
+    /// Search for `key` and cache the path it finds.
+    fn find(cache: &mut PathCache, key: PathKey) {
+        cache.start(key, task(), Entity::from_raw(0), 0);
+        let id = search_id(cache, &key);
+        cache.complete(&id, Some(&[key.from, key.to]));
+    }
+
+    #[test]
+    fn least_recently_used_path_is_dropped() {
+        let mut cache = PathCache::new(2);
+        find(&mut cache, key(0));
+        find(&mut cache, key(1));
+        assert!(cache.get(&key(0)).is_some());
+
+        find(&mut cache, key(2));
+        assert_eq!(cache.get(&key(0)), Some(vec![key(0).from, key(0).to]));
+        assert_eq!(cache.get(&key(1)), None);
+        assert!(cache.get(&key(2)).is_some());
+    }
+
+    #[test]
+    fn tile_changes_invalidate_paths_and_running_searches() {
+        let mut cache = PathCache::new(4);
+        find(&mut cache, key(0));
+        cache.start(key(1), task(), Entity::from_raw(1), 1);
+        let id = search_id(&mut cache, &key(1));
+
+        cache.invalidate();
+        assert_eq!(cache.get(&key(0)), None);
+        // Requests after the change search again instead of waiting for the outdated search
+        assert!(!cache.join(&key(1), Entity::from_raw(2), 2));
+
+        // The outdated search still answers the actor that started it, but is not cached
+        let waiting = cache.complete(&id, Some(&[key(1).from, key(1).to]));
+        assert_eq!(waiting, vec![(Entity::from_raw(1), 1)]);
+        assert_eq!(cache.get(&key(1)), None);
+    }
+
+    #[test]
+    fn identical_requests_join_the_running_search() {
+        let mut cache = PathCache::new(4);
+        cache.start(key(0), task(), Entity::from_raw(0), 0);
+        assert!(cache.join(&key(0), Entity::from_raw(1), 3));
+        assert!(!cache.join(&key(1), Entity::from_raw(2), 4));
+        assert_eq!(cache.searching(), 1);
+
+        let id = search_id(&mut cache, &key(0));
+        let waiting = cache.complete(&id, Some(&[key(0).from, key(0).to]));
+        assert_eq!(
+            waiting,
+            vec![(Entity::from_raw(0), 0), (Entity::from_raw(1), 3)]
+        );
+        assert_eq!(cache.searching(), 0);
+        assert!(cache.get(&key(0)).is_some());
+    }
+}
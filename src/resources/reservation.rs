@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::components::path_finding::grid::GridPosition;
+
+/// Who claimed which tile at which step, shared with the searches running in the background.
+#[derive(Clone, Default)]
+pub struct Slots(HashMap<(GridPosition, u32), Entity>);
+
+impl Slots {
+    /// Whether `entity` may stand on `position` at `step`.
+    pub fn is_free(&self, position: &GridPosition, step: u32, entity: Entity) -> bool {
+        self.0
+            .get(&(*position, step))
+            .map_or(true, |owner| *owner == entity)
+    }
+
+    /// Whether moving from `from` to `to` right after `step` swaps places with another actor
+    /// that moves the opposite way at the same time.
+    pub fn swaps(&self, from: &GridPosition, to: &GridPosition, step: u32, entity: Entity) -> bool {
+        match self.0.get(&(*to, step)) {
+            Some(owner) if *owner != entity => self.0.get(&(*from, step + 1)) == Some(owner),
+            _ => false,
+        }
+    }
+}
+
+struct Reservation {
+    slots: Vec<(GridPosition, u32)>,
+    /// Step at which the actor has to plan ahead again.
+    renew: Option<u32>,
+}
+
+/// Tiles that actors claimed for the steps of a shared clock, so cooperative searches plan
+/// around each other instead of through each other.
+/// A step is the time an actor takes to walk from one tile to the next.
+#[derive(Default)]
+pub struct ReservationTable {
+    now: u32,
+    slots: Arc<Slots>,
+    reservations: HashMap<Entity, Reservation>,
+}
+
+impl ReservationTable {
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    pub fn slots(&self) -> Arc<Slots> {
+        self.slots.clone()
+    }
+
+    /// Claim the tiles of a path that starts at step `start` and takes a step per tile,
+    /// replacing the previous claims of the actor.
+    pub fn reserve(
+        &mut self,
+        entity: Entity,
+        path: &[GridPosition],
+        start: u32,
+        renew: Option<u32>,
+    ) {
+        self.release(entity);
+
+        let now = self.now;
+        let claimed = path
+            .iter()
+            .zip(start..)
+            .map(|(position, step)| (*position, step))
+            .filter(|(_, step)| *step >= now)
+            .collect::<Vec<_>>();
+
+        let slots = Arc::make_mut(&mut self.slots);
+        for slot in &claimed {
+            slots.0.insert(*slot, entity);
+        }
+        self.reservations.insert(
+            entity,
+            Reservation {
+                slots: claimed,
+                renew,
+            },
+        );
+    }
+
+    pub fn release(&mut self, entity: Entity) {
+        let reservation = match self.reservations.remove(&entity) {
+            Some(reservation) => reservation,
+            None => return,
+        };
+
+        let slots = Arc::make_mut(&mut self.slots);
+        for slot in reservation.slots {
+            if slots.0.get(&slot) == Some(&entity) {
+                slots.0.remove(&slot);
+            }
+        }
+    }
+
+    /// Whether a path that starts at step `start` runs into tiles other actors claimed,
+    /// or swaps places with one of them.
+    pub fn conflicts(&self, entity: Entity, path: &[GridPosition], start: u32) -> bool {
+        let blocked = path
+            .iter()
+            .zip(start..)
+            .any(|(position, step)| !self.slots.is_free(position, step, entity));
+        let swapped = path
+            .windows(2)
+            .zip(start..)
+            .any(|(step, at)| self.slots.swaps(&step[0], &step[1], at, entity));
+
+        blocked || swapped
+    }
+
+    /// Move the clock to `now` and forget the claims on earlier steps.
+    /// Returns the actors that have to plan ahead again, each of them only once.
+    pub fn advance(&mut self, now: u32) -> Vec<Entity> {
+        if now <= self.now {
+            return Vec::new();
+        }
+        self.now = now;
+
+        Arc::make_mut(&mut self.slots)
+            .0
+            .retain(|(_, step), _| *step >= now);
+
+        let mut renew = Vec::new();
+        for (entity, reservation) in self.reservations.iter_mut() {
+            reservation.slots.retain(|(_, step)| *step >= now);
+            if reservation.renew.map_or(false, |step| step <= now) {
+                reservation.renew = None;
+                renew.push(*entity);
+            }
+        }
+
+        renew
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ecs_tilemap::map::TilemapSize;
+use bevy_ecs_tilemap::tiles::TileStorage;
+use bevy_prototype_lyon::prelude::DrawMode;
+use serde::{Deserialize, Serialize};
+
+use crate::actor::create_actor;
+use crate::components::dna::Dna;
+use crate::components::path_finding::grid::{Floor, Glyph, GridPosition};
+use crate::components::path_finding::path::{
+    Chase, Destination, PathAlgorithm, PathLeg, PathLegs, PathPriority, PlannedFor, RestoredPath,
+};
+use crate::components::steering::behaviour::{FollowPath, Pursuit};
+use crate::components::steering::boid::{Mass, MaxForce, MaxSpeed, Velocity};
+use crate::map::asset::MapDefinition;
+use crate::map::{floor_rows, world2d_to_grid, MapHandle, MapSize, PaintTiles};
+use crate::systems::debug::color;
+
+const SNAPSHOT_PATH: &str = "snapshots/quicksave.ron";
+
+/// Version of the snapshot format written by this build.
+/// Bump it whenever `Snapshot` or any of the types it contains changes,
+/// snapshots with another version are rejected instead of being loaded.
+///
+/// Version history:
+/// 1. Tiles per floor and actors with their steering settings, destination and path.
+/// 2. Tiles are stored as rows of legend glyphs.
+/// 3. Actors keep their path priority and algorithm, and who they chase or pursue.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(save_snapshot)
+            // Load before the path finding systems run, so they never see the replaced actors
+            .add_system_to_stage(CoreStage::PreUpdate, load_snapshot.after(InputSystem));
+    }
+}
+
+/// The complete state of a running simulation, stored as RON.
+///
+/// Tiles are stored per floor as rows of legend glyphs, like the floor files of the map.
+/// Positions of actors and their paths are in world coordinates.
+/// A snapshot can only be restored on the map it was taken on.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub map: String,
+    pub size: (u32, u32),
+    pub floors: Vec<Vec<String>>,
+    pub actors: Vec<ActorSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ActorSnapshot {
+    pub position: (f32, f32),
+    pub floor: u32,
+    pub color: [f32; 4],
+    pub dna: u64,
+    pub velocity: (f32, f32),
+    pub mass: f32,
+    pub max_speed: f32,
+    pub max_force: f32,
+    pub destination: Option<PositionSnapshot>,
+    pub follow_path: Option<FollowPathSnapshot>,
+    /// Legs of the path on other floors, walked after `follow_path`.
+    pub legs: Vec<LegSnapshot>,
+    pub priority: Option<PathPriority>,
+    pub algorithm: Option<PathAlgorithm>,
+    /// Index of the chased actor in `Snapshot::actors`.
+    pub chase: Option<usize>,
+    pub pursuit: Option<PursuitSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PositionSnapshot {
+    pub tile: (u32, u32),
+    pub floor: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FollowPathSnapshot {
+    pub path: Vec<(f32, f32)>,
+    pub path_width: f32,
+    pub lookahead: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LegSnapshot {
+    pub floor: u32,
+    pub path: Vec<(f32, f32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PursuitSnapshot {
+    pub target_position: (f32, f32),
+    pub target_velocity: (f32, f32),
+    pub lookahead: f32,
+}
+
+/// Only the version is read first, so snapshots in another format are rejected before parsing the rest.
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Ron(ron::Error),
+    UnsupportedVersion { found: u32, expected: u32 },
+    MapMismatch { found: String, expected: String },
+    MapNotLoaded,
+    ActorOutsideMap { actor: usize },
+    PathTooShort { actor: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "could not access snapshot: {}", error),
+            SnapshotError::Ron(error) => write!(f, "invalid snapshot: {}", error),
+            SnapshotError::UnsupportedVersion { found, expected } => write!(
+                f,
+                "snapshot has format version {}, this build only reads version {}",
+                found, expected
+            ),
+            SnapshotError::MapMismatch { found, expected } => write!(
+                f,
+                "snapshot was taken on map {}, but map {} is loaded",
+                found, expected
+            ),
+            SnapshotError::MapNotLoaded => write!(f, "no map is loaded yet"),
+            SnapshotError::ActorOutsideMap { actor } => {
+                write!(f, "actor {} is outside the map", actor)
+            }
+            SnapshotError::PathTooShort { actor } => {
+                write!(f, "actor {} has a path with fewer than two points", actor)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<ron::Error> for SnapshotError {
+    fn from(error: ron::Error) -> Self {
+        SnapshotError::Ron(error)
+    }
+}
+
+impl Snapshot {
+    pub fn read(path: &Path) -> Result<Snapshot, SnapshotError> {
+        let text = fs::read_to_string(path)?;
+
+        let header: SnapshotHeader = ron::from_str(&text)?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion {
+                found: header.version,
+                expected: SNAPSHOT_VERSION,
+            });
+        }
+
+        Ok(ron::from_str(&text)?)
+    }
+
+    /// Checks that every actor, its destination and its paths fit on a map of the given size.
+    /// Errors name actors by their index in `actors`.
+    pub fn check_actors(&self, size: &MapSize) -> Result<(), SnapshotError> {
+        for (index, actor) in self.actors.iter().enumerate() {
+            let position = Vec2::new(actor.position.0, actor.position.1);
+            let on_map = world2d_to_grid(&position).map_or(false, |tile| {
+                size.contains(&GridPosition::new(tile, actor.floor))
+            });
+            let destination_on_map = actor.destination.as_ref().map_or(true, |destination| {
+                let tile = UVec2::new(destination.tile.0, destination.tile.1);
+                size.contains(&GridPosition::new(tile, destination.floor))
+            });
+            let legs_on_map = actor.legs.iter().all(|leg| leg.floor < size.floors);
+            if !on_map || !destination_on_map || !legs_on_map {
+                return Err(SnapshotError::ActorOutsideMap { actor: index });
+            }
+
+            // Following a path takes at least one segment
+            let mut paths = actor
+                .follow_path
+                .iter()
+                .map(|follow_path| &follow_path.path)
+                .chain(actor.legs.iter().map(|leg| &leg.path));
+            if paths.any(|path| path.len() < 2) {
+                return Err(SnapshotError::PathTooShort { actor: index });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), SnapshotError> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        // Every floor and every actor on a single line keeps the file readable in diffs
+        let config = ron::ser::PrettyConfig::new().depth_limit(2);
+        let text = ron::ser::to_string_pretty(self, config)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+fn current_map<'a>(
+    map_handle: &Option<Res<MapHandle>>,
+    maps: &'a Assets<MapDefinition>,
+) -> Result<&'a MapDefinition, SnapshotError> {
+    map_handle
+        .as_ref()
+        .and_then(|handle| maps.get(&handle.0))
+        .ok_or(SnapshotError::MapNotLoaded)
+}
+
+fn save_snapshot(
+    keyboard: Res<Input<KeyCode>>,
+    map_handle: Option<Res<MapHandle>>,
+    maps: Res<Assets<MapDefinition>>,
+    tilemap_query: Query<(&Floor, &TilemapSize, &TileStorage)>,
+    glyph_query: Query<&Glyph>,
+    actor_query: Query<(
+        Entity,
+        &Transform,
+        &Floor,
+        &DrawMode,
+        &Dna,
+        &Velocity,
+        &Mass,
+        &MaxSpeed,
+        &MaxForce,
+        Option<&Destination>,
+        Option<&FollowPath>,
+        Option<&PathLegs>,
+        // Queries take at most 15 components, the rest are grouped
+        (
+            Option<&PathPriority>,
+            Option<&PathAlgorithm>,
+            Option<&Chase>,
+            Option<&Pursuit>,
+        ),
+    )>,
+) {
+    if !keyboard.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let map = match current_map(&map_handle, &maps) {
+        Ok(map) => map,
+        Err(error) => {
+            error!("Could not save snapshot: {}", error);
+            return;
+        }
+    };
+
+    let mut tilemaps = tilemap_query.iter().collect::<Vec<_>>();
+    tilemaps.sort_by_key(|(floor, _, _)| floor.0);
+    let floors = tilemaps
+        .into_iter()
+        .map(|(_, size, storage)| floor_rows(size, storage, &glyph_query))
+        .collect();
+
+    // Chased actors are stored by their index, entities change when a snapshot is loaded
+    let indices = actor_query
+        .iter()
+        .enumerate()
+        .map(|(index, (entity, ..))| (entity, index))
+        .collect::<HashMap<_, _>>();
+
+    let actors = actor_query
+        .iter()
+        .map(
+            |(
+                _,
+                transform,
+                floor,
+                draw_mode,
+                dna,
+                velocity,
+                mass,
+                max_speed,
+                max_force,
+                destination,
+                follow_path,
+                legs,
+                (priority, algorithm, chase, pursuit),
+            )| ActorSnapshot {
+                position: (transform.translation.x, transform.translation.y),
+                floor: floor.0,
+                color: color(draw_mode).as_rgba_f32(),
+                dna: dna.0,
+                velocity: (velocity.0.x, velocity.0.y),
+                mass: mass.0,
+                max_speed: max_speed.0,
+                max_force: max_force.0,
+                destination: destination.map(|destination| PositionSnapshot {
+                    tile: (destination.0.tile.x, destination.0.tile.y),
+                    floor: destination.0.floor,
+                }),
+                follow_path: follow_path.map(|follow_path| FollowPathSnapshot {
+                    path: points_to_snapshot(&follow_path.path),
+                    path_width: follow_path.path_width,
+                    lookahead: follow_path.lookahead,
+                }),
+                legs: legs.map_or_else(Vec::new, |legs| {
+                    legs.0
+                        .iter()
+                        .map(|leg| LegSnapshot {
+                            floor: leg.floor,
+                            path: points_to_snapshot(&leg.path),
+                        })
+                        .collect()
+                }),
+                priority: priority.copied(),
+                algorithm: algorithm.copied(),
+                chase: chase.and_then(|chase| indices.get(&chase.target).copied()),
+                pursuit: pursuit.map(|pursuit| PursuitSnapshot {
+                    target_position: (
+                        pursuit.target_current_position.x,
+                        pursuit.target_current_position.y,
+                    ),
+                    target_velocity: (
+                        pursuit.target_current_velocity.x,
+                        pursuit.target_current_velocity.y,
+                    ),
+                    lookahead: pursuit.lookahead,
+                }),
+            },
+        )
+        .collect();
+
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        map: map.name.clone(),
+        size: (map.size.x, map.size.y),
+        floors,
+        actors,
+    };
+
+    match snapshot.write(Path::new(SNAPSHOT_PATH)) {
+        Ok(()) => info!("Saved snapshot to {}", SNAPSHOT_PATH),
+        Err(error) => error!("Could not save snapshot: {}", error),
+    }
+}
+
+fn load_snapshot(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    map_handle: Option<Res<MapHandle>>,
+    maps: Res<Assets<MapDefinition>>,
+    actor_query: Query<Entity, With<Dna>>,
+    mut paint: EventWriter<PaintTiles>,
+) {
+    if !keyboard.just_pressed(KeyCode::F9) {
+        return;
+    }
+
+    let snapshot = match current_map(&map_handle, &maps).and_then(|map| {
+        let snapshot = Snapshot::read(Path::new(SNAPSHOT_PATH))?;
+        if snapshot.map != map.name || snapshot.size != (map.size.x, map.size.y) {
+            return Err(SnapshotError::MapMismatch {
+                found: snapshot.map,
+                expected: map.name.clone(),
+            });
+        }
+        snapshot.check_actors(&MapSize::from(map))?;
+        Ok(snapshot)
+    }) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            error!("Could not load snapshot {}: {}", SNAPSHOT_PATH, error);
+            return;
+        }
+    };
+
+    let tiles = snapshot
+        .floors
+        .iter()
+        .enumerate()
+        .flat_map(|(floor, rows)| {
+            rows.iter().enumerate().flat_map(move |(y, row)| {
+                row.chars().enumerate().map(move |(x, glyph)| {
+                    let tile = UVec2::new(x as u32, y as u32);
+                    (GridPosition::new(tile, floor as u32), glyph)
+                })
+            })
+        })
+        .collect();
+    paint.send(PaintTiles(tiles));
+
+    for entity in actor_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let mut entities = Vec::with_capacity(snapshot.actors.len());
+    for actor in &snapshot.actors {
+        let [r, g, b, a] = actor.color;
+        let position = Vec2::new(actor.position.0, actor.position.1);
+        let mut entity = create_actor(
+            &mut commands,
+            position,
+            actor.floor,
+            Color::rgba(r, g, b, a),
+        );
+        entity
+            .insert(Dna(actor.dna))
+            .insert(Velocity(Vec2::new(actor.velocity.0, actor.velocity.1)))
+            .insert(Mass(actor.mass))
+            .insert(MaxSpeed(actor.max_speed))
+            .insert(MaxForce(actor.max_force));
+        entities.push(entity.id());
+
+        if let Some(priority) = actor.priority {
+            entity.insert(priority);
+        }
+        if let Some(algorithm) = actor.algorithm {
+            entity.insert(algorithm);
+        }
+        if let Some(pursuit) = &actor.pursuit {
+            let (x, y) = pursuit.target_position;
+            let (dx, dy) = pursuit.target_velocity;
+            entity.insert(Pursuit {
+                target_current_position: Vec2::new(x, y),
+                target_current_velocity: Vec2::new(dx, dy),
+                lookahead: pursuit.lookahead,
+            });
+        }
+
+        if let Some(follow_path) = &actor.follow_path {
+            entity.insert(FollowPath::new(
+                points_from_snapshot(&follow_path.path),
+                follow_path.path_width,
+                follow_path.lookahead,
+            ));
+        }
+
+        if !actor.legs.is_empty() {
+            entity.insert(PathLegs(
+                actor
+                    .legs
+                    .iter()
+                    .map(|leg| PathLeg {
+                        floor: leg.floor,
+                        path: points_from_snapshot(&leg.path),
+                        depart: 0,
+                    })
+                    .collect(),
+            ));
+        }
+
+        if let Some(destination) = &actor.destination {
+            let tile = UVec2::new(destination.tile.0, destination.tile.1);
+            entity.insert(Destination(GridPosition::new(tile, destination.floor)));
+
+            // Actors that were still waiting for a path search for it again
+            if actor.follow_path.is_some() || !actor.legs.is_empty() {
+                entity
+                    .insert(RestoredPath)
+                    .insert(PlannedFor(GridPosition::new(tile, destination.floor)));
+            }
+        }
+    }
+
+    // Targets are only known once every actor is spawned
+    for (actor, entity) in snapshot.actors.iter().zip(&entities) {
+        if let Some(target) = actor.chase.and_then(|index| entities.get(index)) {
+            commands.entity(*entity).insert(Chase::new(*target));
+        }
+    }
+
+    info!(
+        "Loaded snapshot {} with {} actors",
+        SNAPSHOT_PATH,
+        snapshot.actors.len()
+    );
+}
+
+fn points_to_snapshot(points: &[Vec2]) -> Vec<(f32, f32)> {
+    points.iter().map(|point| (point.x, point.y)).collect()
+}
+
+fn points_from_snapshot(points: &[(f32, f32)]) -> Vec<Vec2> {
+    points.iter().map(|(x, y)| Vec2::new(*x, *y)).collect()
+}
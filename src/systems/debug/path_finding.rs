@@ -1 +1,41 @@
+use bevy::prelude::*;
+use bevy_ecs_tilemap::map::TilemapId;
+use bevy_ecs_tilemap::tiles::{TileColor, TilePos, TileStorage};
 
+use crate::components::path_finding::grid::{Floor, GridPosition};
+use crate::resources::nav_grid::Navigation;
+
+/// Tint walkable tiles by the region they belong to, toggled with G.
+pub fn show_regions(
+    keyboard: Res<Input<KeyCode>>,
+    mut enabled: Local<bool>,
+    navigation: Res<Navigation>,
+    tilemap_query: Query<&Floor, With<TileStorage>>,
+    mut tile_query: Query<(&TilePos, &TilemapId, &mut TileColor)>,
+) {
+    let toggled = keyboard.just_pressed(KeyCode::G);
+    if toggled {
+        *enabled = !*enabled;
+    }
+
+    if !toggled && !(*enabled && navigation.is_changed()) {
+        return;
+    }
+
+    for (tile_pos, tilemap_id, mut color) in tile_query.iter_mut() {
+        let region = tilemap_query.get(tilemap_id.0).ok().and_then(|floor| {
+            let position = GridPosition::new(UVec2::new(tile_pos.x, tile_pos.y), floor.0);
+            navigation.grid.region(&position)
+        });
+
+        color.0 = match region {
+            Some(region) if *enabled => region_color(region),
+            _ => Color::WHITE,
+        };
+    }
+}
+
+/// Neighbouring region labels get hues far apart on the color wheel.
+fn region_color(region: u32) -> Color {
+    Color::hsl((region as f32 * 137.5) % 360.0, 0.6, 0.7)
+}
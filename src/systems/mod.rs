@@ -1,5 +1,4 @@
 pub mod debug;
-pub mod drawing;
 pub mod map;
 pub mod path_finding;
 pub mod steering;
@@ -0,0 +1,112 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+
+use crate::components::path_finding::grid::{Floor, GridPosition};
+use crate::components::path_finding::path::{Chase, Destination, FoundPath, PathLegs};
+use crate::components::steering::behaviour::{FollowPath, Pursuit, Seek};
+use crate::components::steering::boid::Velocity;
+use crate::map::world2d_to_grid;
+
+use super::queue::ChaseSettings;
+
+/// Frames ahead that a pursuit aims at.
+const PURSUIT_LOOKAHEAD: f32 = 10.0;
+
+/// Point the destination of chasing actors at the tile of their target, and let them pursue
+/// the target once they are close.
+/// A new path is only requested once the target moved far enough from where the current path
+/// ends, and at most `ChaseSettings::max_replans` each frame, longest waiting first.
+#[allow(clippy::type_complexity)]
+pub fn chase_targets(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<ChaseSettings>,
+    mut chaser_query: Query<(
+        Entity,
+        &mut Chase,
+        &Transform,
+        &Floor,
+        Option<&Destination>,
+        Option<&Pursuit>,
+    )>,
+    target_query: Query<(&Transform, Option<&Floor>, Option<&Velocity>)>,
+) {
+    let now = time.seconds_since_startup();
+    let mut waiting = Vec::new();
+
+    for (entity, chase, transform, floor, destination, pursuit) in chaser_query.iter() {
+        let (target_transform, target_floor, target_velocity) = match target_query.get(chase.target)
+        {
+            Ok(target) => target,
+            // The target was despawned, there is nothing left to chase
+            Err(_) => {
+                commands
+                    .entity(entity)
+                    .remove::<Chase>()
+                    .remove::<Destination>()
+                    .remove::<Pursuit>()
+                    .remove::<Seek>();
+                continue;
+            }
+        };
+        let target_position = target_transform.translation.truncate();
+        let target_floor = target_floor.map_or(floor.0, |target_floor| target_floor.0);
+        let distance = if target_floor == floor.0 {
+            transform.translation.truncate().distance(target_position)
+        } else {
+            f32::INFINITY
+        };
+
+        // Pursuits end further out than they start, so they do not flicker at the edge
+        let range = if pursuit.is_some() {
+            2.0 * settings.pursuit_range
+        } else {
+            settings.pursuit_range
+        };
+        if distance <= range {
+            commands
+                .entity(entity)
+                .remove::<Destination>()
+                .remove::<FoundPath>()
+                .remove::<PathLegs>()
+                .remove::<FollowPath>()
+                .insert(Pursuit {
+                    target_current_position: target_position,
+                    target_current_velocity: target_velocity
+                        .map_or(Vec2::ZERO, |velocity| velocity.0),
+                    lookahead: PURSUIT_LOOKAHEAD,
+                });
+            continue;
+        }
+        if pursuit.is_some() {
+            commands.entity(entity).remove::<Pursuit>().remove::<Seek>();
+        }
+
+        let tile = match world2d_to_grid(&target_position) {
+            Some(tile) => GridPosition::new(tile, target_floor),
+            None => continue,
+        };
+        let moved = match (destination, chase.planned) {
+            (Some(_), Some(planned)) => {
+                let dx = planned.tile.x.abs_diff(tile.tile.x);
+                let dy = planned.tile.y.abs_diff(tile.tile.y);
+                planned.floor != tile.floor || dx.max(dy) > settings.replan_distance
+            }
+            _ => true,
+        };
+        let rested = now - chase.requested_at >= settings.replan_interval.as_secs_f64();
+        if moved && rested {
+            waiting.push((chase.requested_at, entity, tile));
+        }
+    }
+
+    waiting.sort_by(|(a, ..), (b, ..)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    for (_, entity, tile) in waiting.into_iter().take(settings.max_replans) {
+        if let Ok((_, mut chase, ..)) = chaser_query.get_mut(entity) {
+            chase.planned = Some(tile);
+            chase.requested_at = now;
+        }
+        commands.entity(entity).insert(Destination(tile));
+    }
+}
@@ -0,0 +1,339 @@
+use std::cmp::{min, Reverse};
+use std::collections::BinaryHeap;
+use std::iter;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::components::path_finding::grid::GridPosition;
+use crate::resources::nav_grid::{NavGrid, OFFSETS};
+
+use super::finder::{heuristic, tie_broken_moves, SearchParameters};
+
+/// Order of the tiles in the queue, smallest first.
+type Key = (u32, u32);
+
+/// D* Lite: a search from the destination back to the actor that is kept once the path is found.
+/// When tiles change, only the part of the search they affect is redone, from wherever the actor
+/// stands by then.
+pub struct DStarLiteSearch {
+    start: GridPosition,
+    goal: GridPosition,
+    /// Where the actor stood when the keys in the queue were computed.
+    last: GridPosition,
+    /// How far the heuristic of the keys in the queue falls short since the actor moved.
+    modifier: u32,
+    /// The weight the heuristic is scaled by, no tile may weigh less.
+    cheapest: u32,
+    parameters: SearchParameters,
+    /// Cost from a tile to the destination, as far as it was searched.
+    g: HashMap<GridPosition, u32>,
+    /// Cost from a tile to the destination through its best move.
+    rhs: HashMap<GridPosition, u32>,
+    queue: BinaryHeap<Reverse<(Key, u32, u32, u32)>>,
+    /// Key of each queued tile, queue entries with another key are outdated.
+    queued: HashMap<GridPosition, Key>,
+}
+
+impl DStarLiteSearch {
+    pub fn new(
+        grid: &NavGrid,
+        from: GridPosition,
+        to: GridPosition,
+        parameters: SearchParameters,
+    ) -> Self {
+        let mut search = DStarLiteSearch {
+            start: from,
+            goal: to,
+            last: from,
+            modifier: 0,
+            cheapest: grid.cheapest_weight(),
+            parameters,
+            g: HashMap::default(),
+            rhs: HashMap::default(),
+            queue: BinaryHeap::new(),
+            queued: HashMap::default(),
+        };
+        search.rhs.insert(to, 0);
+        search.push(to);
+
+        search
+    }
+
+    /// Search until the cost from the actor to the destination is known.
+    /// `expand` is called for each tile that is expanded, the search stops when it returns false.
+    /// Returns whether the search finished.
+    pub fn compute<E: FnMut() -> bool>(&mut self, grid: &NavGrid, mut expand: E) -> bool {
+        while let Some(Reverse((key, floor, y, x))) = self.queue.peek().copied() {
+            let position = GridPosition::new(UVec2::new(x, y), floor);
+            if self.queued.get(&position) != Some(&key) {
+                self.queue.pop();
+                continue;
+            }
+
+            let consistent = self.g(&self.start) == self.rhs(&self.start);
+            if key >= self.key(&self.start) && consistent {
+                break;
+            }
+            if !expand() {
+                return false;
+            }
+
+            self.queue.pop();
+            self.queued.remove(&position);
+            if key < self.key(&position) {
+                self.push(position);
+            } else if self.g(&position) > self.rhs(&position) {
+                self.g.insert(position, self.rhs(&position));
+                for (previous, _) in grid.predecessors(&position, self.parameters.corner_cutting) {
+                    self.update_tile(grid, previous);
+                }
+            } else {
+                self.g.remove(&position);
+                self.update_tile(grid, position);
+                for (previous, _) in grid.predecessors(&position, self.parameters.corner_cutting) {
+                    self.update_tile(grid, previous);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Take the `changed` tiles into account for the next `compute`, the actor now stands on
+    /// `position`.
+    pub fn update<I>(&mut self, grid: &NavGrid, position: GridPosition, changed: I)
+    where
+        I: IntoIterator<Item = GridPosition>,
+    {
+        // A tile that is cheaper than any before would make the heuristic overestimate
+        if grid.cheapest_weight() < self.cheapest {
+            *self = DStarLiteSearch::new(grid, position, self.goal, self.parameters);
+            return;
+        }
+
+        self.start = position;
+        self.modifier += self.estimate(&self.last, &self.start);
+        self.last = self.start;
+
+        for tile in changed {
+            // The moves onto a changed tile, and the diagonals past it, start around it
+            let around = OFFSETS.iter().filter_map(|(dx, dy)| tile.offset(*dx, *dy));
+            let stairs = [tile.floor.checked_sub(1), tile.floor.checked_add(1)]
+                .into_iter()
+                .flatten()
+                .map(|floor| GridPosition::new(tile.tile, floor));
+
+            for position in iter::once(tile).chain(around).chain(stairs) {
+                if grid.contains(&position) {
+                    self.update_tile(grid, position);
+                }
+            }
+        }
+    }
+
+    /// The path from where the actor stood at the last update, if the destination can be reached.
+    pub fn path(&self, grid: &NavGrid) -> Option<Vec<GridPosition>> {
+        if self.g(&self.start) == u32::MAX {
+            return None;
+        }
+
+        let mut path = vec![self.start];
+        let mut current = self.start;
+        while current != self.goal {
+            // Costs fall towards the destination, a path through more tiles than were searched
+            // goes round in circles
+            if path.len() > self.g.len() {
+                return None;
+            }
+
+            let (next, _) = tie_broken_moves(grid, &current, &self.parameters)
+                .into_iter()
+                .map(|(next, cost)| (next, cost.saturating_add(self.g(&next))))
+                .min_by_key(|(next, cost)| (*cost, next.floor, next.tile.y, next.tile.x))?;
+            path.push(next);
+            current = next;
+        }
+
+        Some(path)
+    }
+
+    fn g(&self, position: &GridPosition) -> u32 {
+        self.g.get(position).copied().unwrap_or(u32::MAX)
+    }
+
+    fn rhs(&self, position: &GridPosition) -> u32 {
+        self.rhs.get(position).copied().unwrap_or(u32::MAX)
+    }
+
+    fn estimate(&self, from: &GridPosition, to: &GridPosition) -> u32 {
+        heuristic(from, to) * self.cheapest / 100
+    }
+
+    fn key(&self, position: &GridPosition) -> Key {
+        let cost = min(self.g(position), self.rhs(position));
+        let estimate = self.estimate(&self.start, position) + self.modifier;
+        (cost.saturating_add(estimate), cost)
+    }
+
+    fn push(&mut self, position: GridPosition) {
+        let key = self.key(&position);
+        self.queued.insert(position, key);
+        self.queue.push(Reverse((
+            key,
+            position.floor,
+            position.tile.y,
+            position.tile.x,
+        )));
+    }
+
+    /// Recompute the cost of `position` through its best move and queue it if that changed.
+    fn update_tile(&mut self, grid: &NavGrid, position: GridPosition) {
+        if position != self.goal {
+            let best = tie_broken_moves(grid, &position, &self.parameters)
+                .into_iter()
+                .map(|(next, cost)| cost.saturating_add(self.g(&next)))
+                .min()
+                .unwrap_or(u32::MAX);
+            self.rhs.insert(position, best);
+        }
+
+        self.queued.remove(&position);
+        if self.g(&position) != self.rhs(&position) {
+            self.push(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pathfinding::prelude::astar;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::resources::nav_grid::CornerCutting;
+    use crate::systems::path_finding::queue::HierarchicalSearch;
+
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    fn random_position(rng: &mut StdRng) -> GridPosition {
+        GridPosition::new(
+            UVec2::new(rng.gen_range(0..SIZE), rng.gen_range(0..SIZE)),
+            0,
+        )
+    }
+
+    /// Tie-broken cost of the cheapest path, searched from scratch.
+    fn fresh_cost(
+        grid: &NavGrid,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> Option<u32> {
+        let cheapest = grid.cheapest_weight();
+        astar(
+            from,
+            |node| tie_broken_moves(grid, node, parameters),
+            |node| heuristic(node, to) * cheapest / 100,
+            |node| node == to,
+        )
+        .map(|(_, cost)| cost)
+    }
+
+    fn path_cost(grid: &NavGrid, path: &[GridPosition], parameters: &SearchParameters) -> u32 {
+        path.windows(2)
+            .map(|step| {
+                tie_broken_moves(grid, &step[0], parameters)
+                    .into_iter()
+                    .find(|(next, _)| *next == step[1])
+                    .map(|(_, cost)| cost)
+                    .expect("steps of a path are moves on the grid")
+            })
+            .sum()
+    }
+
+    #[test]
+    fn same_cost_as_a_fresh_search_after_changes() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let (mut found, mut unreachable) = (0, 0);
+
+        for seed in 0..200 {
+            let parameters = SearchParameters {
+                seed,
+                corner_cutting: CornerCutting::Never,
+                hierarchical: HierarchicalSearch::default(),
+                timeout: Duration::MAX,
+            };
+            let from = random_position(&mut rng);
+            let to = random_position(&mut rng);
+            let mut grid = NavGrid::new(UVec2::splat(SIZE), 1);
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let position = GridPosition::new(UVec2::new(x, y), 0);
+                    grid.set_walkable(&position, rng.gen_bool(0.75));
+                }
+            }
+            grid.set_walkable(&from, true);
+            grid.set_walkable(&to, true);
+
+            let mut search = DStarLiteSearch::new(&grid, from, to, parameters);
+            assert!(search.compute(&grid, || true));
+            let path = search.path(&grid);
+            assert_eq!(
+                path.as_ref()
+                    .map(|path| path_cost(&grid, path, &parameters)),
+                fresh_cost(&grid, &from, &to, &parameters),
+                "from {:?} to {:?}",
+                from,
+                to
+            );
+
+            // The actor walks part of the way, then walls go up and come down and weights change
+            let position = path.map_or(from, |path| path[path.len() / 3]);
+            let mut changed = Vec::new();
+            for _ in 0..12 {
+                let tile = random_position(&mut rng);
+                if tile == position || tile == to {
+                    continue;
+                }
+                match rng.gen_range(0..3) {
+                    0 => grid.set_walkable(&tile, false),
+                    1 => grid.set_walkable(&tile, true),
+                    _ => grid.set_weight(&tile, [1.5, 2.0, 3.0][rng.gen_range(0..3)]),
+                }
+                changed.push(tile);
+            }
+            search.update(&grid, position, changed);
+            assert!(search.compute(&grid, || true));
+
+            let expected = fresh_cost(&grid, &position, &to, &parameters);
+            let path = search.path(&grid);
+            match (&path, expected) {
+                (Some(path), Some(expected)) => {
+                    assert_eq!(path.first(), Some(&position));
+                    assert_eq!(path.last(), Some(&to));
+                    assert_eq!(
+                        path_cost(&grid, path, &parameters),
+                        expected,
+                        "from {:?} to {:?}",
+                        position,
+                        to
+                    );
+                    found += 1;
+                }
+                (None, None) => unreachable += 1,
+                (path, expected) => panic!(
+                    "from {:?} to {:?}: D* Lite found {:?}, A* found {:?}",
+                    position, to, path, expected
+                ),
+            }
+        }
+
+        // Both outcomes have to be covered for the comparison to mean anything
+        assert!(found > 0 && unreachable > 0);
+    }
+}
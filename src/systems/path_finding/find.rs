@@ -1,237 +1,648 @@
-use std::cmp::{max, min};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
-use std::mem;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::iter;
 
+use bevy::diagnostic::Diagnostics;
 use bevy::tasks::AsyncComputeTaskPool;
-use bevy::utils::hashbrown::HashMap;
-use bevy::utils::Instant;
+use bevy::utils::hashbrown::HashSet;
+use bevy::utils::{HashMap, Instant};
 use bevy::{log, prelude::*};
-use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
-use bevy_inspector_egui::egui::remap;
 use futures_lite::future;
 use pathfinding::prelude::*;
 
 use crate::components::dna::Dna;
-use crate::components::path_finding::grid::Walkable;
+use crate::components::path_finding::grid::{Floor, GridPosition};
 use crate::components::path_finding::path::*;
-use crate::map::world2d_to_grid;
-use crate::resources::nav_mesh::{Move, NavMesh};
+use crate::components::steering::behaviour::{FollowFlowField, FollowPath};
+use crate::events::path_finding::{PathFailed, PathFailure, PathFound, PathRequested};
+use crate::map::raycast::line_of_sight;
+use crate::map::{world2d_to_grid, TileChanged};
+use crate::resources::nav_grid::{CornerCutting, NavGrid, Navigation};
+use crate::resources::path_cache::{MovementProfile, PathCache, PathKey};
+use crate::resources::reservation::ReservationTable;
 
-use super::{DIAGONAL_COST, STRAIGHT_COST};
-
-struct PathFindingRequest {
-    from: UVec2,
-    to: UVec2,
-    seed: u64,
-}
-
-#[derive(Default)]
-pub struct PathFindingRequests {
-    requests: HashMap<Entity, PathFindingRequest>,
-}
-
-#[derive(Default)]
-pub struct Navigation {
-    pub mesh: Arc<Mutex<NavMesh>>,
-}
-
-impl PathFindingRequests {
-    fn request(&mut self, entity: Entity, req: PathFindingRequest) {
-        self.requests.insert(entity, req);
-    }
-
-    fn take(&mut self) -> HashMap<Entity, PathFindingRequest> {
-        mem::take(&mut self.requests)
-    }
-}
+use super::finder::{heuristic, Cooperative, DStarLite, PathFinder, SearchParameters};
+use super::queue::{
+    CooperativeSearch, HierarchicalSearch, PathCacheSettings, PathFindingRequest,
+    PathFindingRequests, RequestFallbacks, SchedulerSettings, PATH_CACHE_HITS, PATH_CACHE_MISSES,
+    PATH_NODES_EXPANDED, PATH_QUEUE_DEPTH, PATH_SEARCH_TIME, PATH_WAIT_TIME,
+};
+use super::{DIAGONAL_COST, STAIRS_COST, STRAIGHT_COST};
 
 pub fn schedule_new_path_finding(
+    mut commands: Commands,
     mut path_finding_tasks: ResMut<PathFindingRequests>,
-    destination_query: Query<(Entity, &Destination, &Transform, &Dna), Added<Destination>>,
+    mut path_requested: EventWriter<PathRequested>,
+    mut path_failed: EventWriter<PathFailed>,
+    destination_query: Query<
+        (
+            Entity,
+            &Destination,
+            &Transform,
+            &Floor,
+            &Dna,
+            Option<&PathPriority>,
+            Option<&PathAlgorithm>,
+            Option<&RestoredPath>,
+            Option<&PlannedFor>,
+        ),
+        Changed<Destination>,
+    >,
 ) {
-    for (entity, destination, transform, dna) in destination_query.iter() {
-        let current_tile = world2d_to_grid(&transform.translation.truncate());
+    for (entity, destination, transform, floor, dna, priority, algorithm, restored, planned_for) in
+        destination_query.iter()
+    {
+        if restored.is_some() {
+            commands.entity(entity).remove::<RestoredPath>();
+            continue;
+        }
+
+        // The search for the previous destination is no longer needed
+        commands
+            .entity(entity)
+            .remove::<PendingPath>()
+            .remove::<IncrementalPlanner>()
+            .remove::<FollowFlowField>();
+        // A path to another destination is not followed while the new one is searched, replans
+        // for the same destination keep walking the old path until the new one is found
+        if planned_for != Some(&PlannedFor(destination.0)) {
+            drop_path(&mut commands, entity);
+        }
 
+        let current_tile = match world2d_to_grid(&transform.translation.truncate()) {
+            Some(tile) => GridPosition::new(tile, floor.0),
+            None => {
+                drop_path(&mut commands, entity);
+                path_finding_tasks.cancel(entity);
+                path_failed.send(PathFailed {
+                    entity,
+                    reason: PathFailure::OutOfBounds,
+                });
+                continue;
+            }
+        };
+
+        path_requested.send(PathRequested {
+            entity,
+            from: current_tile,
+            to: destination.0,
+        });
         path_finding_tasks.request(
             entity,
             PathFindingRequest {
                 from: current_tile,
                 to: destination.0,
                 seed: dna.0,
+                priority: priority.copied().unwrap_or_default(),
+                algorithm: algorithm.copied(),
             },
         );
+    }
+}
 
-        // commands.entity(entity).remove::<Destination>();
+/// Forget the requests of actors that lost their destination or were despawned.
+/// Runs in `CoreStage::PostUpdate`, where the removals of the update stage are visible.
+pub fn cancel_path_finding(
+    mut commands: Commands,
+    removed: RemovedComponents<Destination>,
+    mut path_finding_tasks: ResMut<PathFindingRequests>,
+    mut reservations: ResMut<ReservationTable>,
+    actor_query: Query<(
+        Option<&Destination>,
+        Option<&PendingPath>,
+        Option<&IncrementalPlanner>,
+        Option<&FollowFlowField>,
+    )>,
+) {
+    for entity in removed.iter() {
+        let (pending, planner, following) = match actor_query.get(entity) {
+            // A destination that was replaced by a new one is scheduled again instead
+            Ok((Some(_), ..)) => continue,
+            Ok((None, pending, planner, following)) => {
+                (pending.is_some(), planner.is_some(), following.is_some())
+            }
+            Err(_) => (false, false, false),
+        };
+
+        path_finding_tasks.cancel(entity);
+        reservations.release(entity);
+        // Dropping the task cancels the search
+        if pending {
+            commands.entity(entity).remove::<PendingPath>();
+        }
+        if planner {
+            commands.entity(entity).remove::<IncrementalPlanner>();
+        }
+        if following {
+            commands.entity(entity).remove::<FollowFlowField>();
+        }
+    }
+}
+
+/// Request a new path for actors whose remaining path crosses a changed tile.
+/// Actors with an `IncrementalPlanner` repair their path instead, as long as the frame budget
+/// of the scheduler lasts.
+#[allow(clippy::type_complexity)]
+pub fn replan_affected_paths(
+    mut commands: Commands,
+    mut tile_changes: EventReader<TileChanged>,
+    navigation: Res<Navigation>,
+    settings: Res<SchedulerSettings>,
+    mut actor_query: Query<(
+        Entity,
+        &mut Destination,
+        &Transform,
+        &Floor,
+        Option<&FollowPath>,
+        Option<&PathLegs>,
+        Option<&mut IncrementalPlanner>,
+    )>,
+    mut path_found: EventWriter<PathFound>,
+) {
+    let changed = tile_changes
+        .iter()
+        .map(|TileChanged(position)| *position)
+        .collect::<HashSet<GridPosition>>();
+    if changed.is_empty() {
+        return;
+    }
+
+    let started = Instant::now();
+    for (entity, mut destination, transform, floor, follow_path, legs, planner) in
+        actor_query.iter_mut()
+    {
+        let current = follow_path.map(|follow_path| (floor.0, &follow_path.path));
+        let upcoming = legs
+            .into_iter()
+            .flat_map(|legs| legs.0.iter())
+            .map(|leg| (leg.floor, &leg.path));
+
+        // Smoothed paths also cross the tiles between their waypoints
+        let crosses_change = current.into_iter().chain(upcoming).any(|(floor, path)| {
+            let tiles = path.iter().filter_map(world2d_to_grid).collect::<Vec<_>>();
+            let unchanged = |tile: UVec2| !changed.contains(&GridPosition::new(tile, floor));
+            tiles.iter().any(|tile| !unchanged(*tile))
+                || tiles
+                    .windows(2)
+                    .any(|segment| !line_of_sight(segment[0], segment[1], unchanged))
+        });
+
+        let position = world2d_to_grid(&transform.translation.truncate())
+            .map(|tile| GridPosition::new(tile, floor.0));
+        let repaired = match (planner, position) {
+            (Some(mut planner), Some(position)) => {
+                // Every change goes into the search, so later repairs start from an up to date one
+                planner
+                    .0
+                    .update(&navigation.grid, position, changed.iter().copied());
+                let finished = crosses_change
+                    && planner.0.compute(&navigation.grid, || {
+                        started.elapsed() < settings.frame_budget
+                    });
+                finished.then(|| planner.0.path(&navigation.grid)).flatten()
+            }
+            _ => None,
+        };
+
+        if !crosses_change {
+            continue;
+        }
+        match repaired {
+            Some(path) => {
+                path_found.send(PathFound {
+                    entity,
+                    destination: path.last().copied().unwrap_or_default(),
+                    cost: path_cost(&navigation.grid, &path),
+                    length: path.len(),
+                });
+                commands
+                    .entity(entity)
+                    .insert(FoundPath(path))
+                    .insert(PlannedFor(destination.0));
+            }
+            // A changed destination schedules a new search from the current position
+            None => destination.set_changed(),
+        }
+    }
+}
+
+/// Move the reservation clock along and request a new path for actors that are halfway through
+/// the steps they planned around other actors for.
+pub fn advance_reservations(
+    time: Res<Time>,
+    cooperative: Res<CooperativeSearch>,
+    mut reservations: ResMut<ReservationTable>,
+    mut destination_query: Query<&mut Destination>,
+) {
+    let now = time.seconds_since_startup() / cooperative.step.as_secs_f64();
+    for entity in reservations.advance(now as u32) {
+        if let Ok(mut destination) = destination_query.get_mut(entity) {
+            destination.set_changed();
+        }
     }
 }
 
 // TODO in new system, upon a new-map event, remove the Path component from all entities that have it
+#[allow(clippy::too_many_arguments)]
 pub fn calculate_paths(
     mut commands: Commands,
-    navigation: Res<Arc<Mutex<NavMesh>>>,
-    mut path_finding_tasks: ResMut<PathFindingRequests>,
-    map_query: Query<&TileStorage>,
-    walkable_query: Query<Entity, With<Walkable>>,
+    navigation: Res<Navigation>,
+    corner_cutting: Res<CornerCutting>,
+    settings: Res<SchedulerSettings>,
+    fallbacks: Res<RequestFallbacks>,
+    hierarchical: Res<HierarchicalSearch>,
+    cooperative: Res<CooperativeSearch>,
+    reservations: Res<ReservationTable>,
+    algorithm: Res<PathAlgorithm>,
+    cache_settings: Res<PathCacheSettings>,
+    mut cache: ResMut<PathCache>,
+    mut requests: ResMut<PathFindingRequests>,
+    pending_query: Query<&PendingPath>,
+    actor_query: Query<&Destination>,
+    mut diagnostics: ResMut<Diagnostics>,
+    mut path_found: EventWriter<PathFound>,
+    mut path_failed: EventWriter<PathFailed>,
 ) {
-    // TODO track the changes of this component and store into resource
-    let walkable_tiles = Arc::new(walkable_query.iter().collect::<Vec<Entity>>());
-
-    let map = Arc::new(map_query.single().clone());
     let pool = AsyncComputeTaskPool::get();
-    let now = Instant::now();
-    let max_duration = Duration::from_millis(1);
-    let requests = path_finding_tasks.take();
-
-    for (entity, request) in requests {
-        let thread_map = map.clone();
-        let thread_mesh = navigation.clone();
-        let walkable_tiles = walkable_tiles.clone();
+    let started = Instant::now();
+    // Actors waiting for a shared search are counted by the search
+    let mut in_flight = pending_query
+        .iter()
+        .filter(|pending_path| pending_path.task.is_some())
+        .count()
+        + cache.searching();
+    let (mut hits, mut misses) = (0, 0);
+    let mut sharing = HashMap::default();
+    if !requests.is_empty() {
+        for destination in actor_query.iter() {
+            *sharing.entry(destination.0).or_insert(0) += 1;
+        }
+    }
+
+    // Whatever does not fit in this frame stays queued for the next one
+    while settings.can_start(in_flight, started) {
+        let (entity, id, mut request, waited) = match requests.pop() {
+            Some(request) => request,
+            None => break,
+        };
+        diagnostics.add_measurement(PATH_WAIT_TIME, waited.as_secs_f64() * 1000.0);
+
+        // The actor was despawned or lost its destination while waiting
+        if actor_query.get(entity).is_err() {
+            continue;
+        }
+
+        let grid = navigation.grid.clone();
+        let start = if !grid.contains(&request.from) || !grid.contains(&request.to) {
+            Err(PathFailure::OutOfBounds)
+        } else {
+            // An actor pushed onto a wall starts from the closest tile it can walk on
+            grid.nearest_walkable(&request.from, fallbacks.snap_radius)
+                .ok_or(PathFailure::StartBlocked)
+        };
+        match start {
+            Ok(start) => request.from = start,
+            Err(reason) => {
+                drop_path(&mut commands, entity);
+                path_failed.send(PathFailed { entity, reason });
+                continue;
+            }
+        }
+
+        let closest_reachable = fallbacks.closest_reachable;
+        // Searching for a destination in another region would only visit every tile it can reach
+        if !closest_reachable && !grid.connected(&request.from, &request.to) {
+            drop_path(&mut commands, entity);
+            path_failed.send(PathFailed {
+                entity,
+                reason: PathFailure::Unreachable,
+            });
+            continue;
+        }
+
+        // A crowd heading to the same place shares a single search from the destination,
+        // unless an actor asked for an algorithm of its own or actors plan around each other
+        let crowded = request.algorithm.is_none()
+            && !cooperative.enabled
+            && sharing.get(&request.to).copied().unwrap_or_default() >= settings.flow_field_actors;
+        if crowded && grid.connected(&request.from, &request.to) {
+            commands
+                .entity(entity)
+                .remove::<FollowPath>()
+                .remove::<PathLegs>()
+                .insert(FollowFlowField {
+                    destination: request.to,
+                });
+            continue;
+        }
+
+        let algorithm = request.algorithm.unwrap_or(*algorithm);
+        let finder = algorithm.finder();
+        let parameters = SearchParameters {
+            seed: request.seed,
+            corner_cutting: *corner_cutting,
+            hierarchical: *hierarchical,
+            timeout: settings.search_timeout,
+        };
+        let cooperation = cooperative.enabled.then(|| Cooperative {
+            finder,
+            slots: reservations.slots(),
+            entity,
+            start: reservations.now(),
+            window: cooperative.window,
+        });
+        let start = cooperation.as_ref().map(|cooperation| cooperation.start);
+
+        // Paths planned around other actors or kept for repairs are not shared
+        let key = PathKey {
+            from: request.from,
+            to: request.to,
+            profile: MovementProfile {
+                algorithm,
+                corner_cutting: *corner_cutting,
+                closest_reachable,
+                seed: cache_settings.vary_by_dna.then(|| request.seed),
+            },
+        };
+        let shared = cache_settings.capacity > 0
+            && cooperation.is_none()
+            && algorithm != PathAlgorithm::DStarLite;
+        if shared {
+            if let Some(path) = cache.get(&key) {
+                hits += 1;
+                path_found.send(PathFound {
+                    entity,
+                    destination: path.last().copied().unwrap_or_default(),
+                    cost: path_cost(&grid, &path),
+                    length: path.len(),
+                });
+                commands
+                    .entity(entity)
+                    .insert(FoundPath(path))
+                    .insert(PlannedFor(request.to));
+                continue;
+            }
+            if cache.join(&key, entity, id) {
+                hits += 1;
+                commands.entity(entity).insert(PendingPath {
+                    task: None,
+                    request: id,
+                    start,
+                });
+                continue;
+            }
+            misses += 1;
+        }
+
+        let snapshot = Navigation::clone(&navigation);
         let task = pool.spawn(async move {
-            // TODO share BuildHasherDefault for each call to successors
-            astar(
-                &request.from,
-                |node| {
-                    add_entity_tie_breaker(
-                        request.seed,
-                        BuildHasherDefault::<DefaultHasher>::default(),
-                        neighbours(&thread_map, &walkable_tiles, thread_mesh.clone(), *node),
-                    )
-                },
-                |node| heuristic(node, &request.to),
-                |node| node.x == request.to.x && node.y == request.to.y,
-            )
-            .map(|path| FoundPath(path.0))
+            let (from, to) = (&request.from, &request.to);
+            let (path, stats, planner) = match &cooperation {
+                Some(cooperation) => {
+                    let (path, stats) = cooperation.find_path(&snapshot, from, to, &parameters);
+                    (path, stats, None)
+                }
+                // Keep the search, so the path can be repaired when tiles change
+                None if algorithm == PathAlgorithm::DStarLite => {
+                    let (path, stats, search) = DStarLite.plan(&snapshot, from, to, &parameters);
+                    let planner = path.is_ok().then(|| search);
+                    (path, stats, planner)
+                }
+                None => {
+                    let (path, stats) = finder.find_path(&snapshot, from, to, &parameters);
+                    (path, stats, None)
+                }
+            };
+            let path = match path {
+                Err(PathFailure::Unreachable) if closest_reachable => path_to_closest_reachable(
+                    &snapshot.grid,
+                    &request.from,
+                    &request.to,
+                    parameters.corner_cutting,
+                )
+                .ok_or(PathFailure::Unreachable),
+                path => path,
+            };
+
+            (path.map(FoundPath), stats, planner)
+        });
+
+        let task = if shared {
+            cache.start(key, task, entity, id);
+            None
+        } else {
+            Some(task)
+        };
+        commands.entity(entity).insert(PendingPath {
+            task,
+            request: id,
+            start,
         });
+        in_flight += 1;
+    }
 
-        commands.entity(entity).insert(PendingPath(task));
+    diagnostics.add_measurement(PATH_QUEUE_DEPTH, requests.len() as f64);
+    diagnostics.add_measurement(PATH_CACHE_HITS, hits as f64);
+    diagnostics.add_measurement(PATH_CACHE_MISSES, misses as f64);
+}
 
-        if now.elapsed() > max_duration {
-            break;
+/// Hand the result of a search shared between identical requests to the actors that waited for it.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_shared_searches(
+    mut commands: Commands,
+    requests: Res<PathFindingRequests>,
+    navigation: Res<Navigation>,
+    mut cache: ResMut<PathCache>,
+    mut diagnostics: ResMut<Diagnostics>,
+    pending_query: Query<&PendingPath>,
+    destination_query: Query<&Destination>,
+    mut path_found: EventWriter<PathFound>,
+    mut path_failed: EventWriter<PathFailed>,
+) {
+    let completed = cache
+        .searches_mut()
+        .filter_map(|(id, task)| {
+            let completion = future::block_on(future::poll_once(task))?;
+            Some((*id, completion))
+        })
+        .collect::<Vec<_>>();
+
+    for (id, (completion, stats, _)) in completed {
+        diagnostics.add_measurement(PATH_SEARCH_TIME, stats.duration.as_secs_f64() * 1000.0);
+        diagnostics.add_measurement(PATH_NODES_EXPANDED, stats.expanded as f64);
+
+        let path = completion.as_ref().ok().map(|path| path.0.as_slice());
+        for (entity, request) in cache.complete(&id, path) {
+            // Actors that moved on to another request no longer wait for this one
+            let waiting = pending_query.get(entity).map_or(false, |pending_path| {
+                pending_path.task.is_none() && pending_path.request == request
+            });
+            if !waiting || !requests.is_latest(entity, request) {
+                continue;
+            }
+
+            let mut entity_commands = commands.entity(entity);
+            entity_commands.remove::<PendingPath>();
+            match &completion {
+                Ok(path) => {
+                    path_found.send(PathFound {
+                        entity,
+                        destination: path.0.last().copied().unwrap_or_default(),
+                        cost: path_cost(&navigation.grid, &path.0),
+                        length: path.0.len(),
+                    });
+                    entity_commands.insert(FoundPath(path.0.clone()));
+                    if let Ok(destination) = destination_query.get(entity) {
+                        entity_commands.insert(PlannedFor(destination.0));
+                    }
+                }
+                Err(reason) => {
+                    entity_commands
+                        .remove::<FollowPath>()
+                        .remove::<PathLegs>()
+                        .remove::<PlannedFor>();
+                    log::info!("Could not find path for entity {:?}: {}", entity, reason);
+                    path_failed.send(PathFailed {
+                        entity,
+                        reason: *reason,
+                    });
+                }
+            }
         }
     }
 }
 
+/// Forget the cached paths when tiles change.
+pub fn invalidate_path_cache(
+    mut tile_changes: EventReader<TileChanged>,
+    mut cache: ResMut<PathCache>,
+) {
+    if tile_changes.iter().count() > 0 {
+        cache.invalidate();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle_completed_path(
     mut commands: Commands,
+    requests: Res<PathFindingRequests>,
+    navigation: Res<Navigation>,
+    cooperative: Res<CooperativeSearch>,
+    mut reservations: ResMut<ReservationTable>,
+    mut diagnostics: ResMut<Diagnostics>,
     mut transform_tasks: Query<(Entity, &mut PendingPath)>,
+    mut destination_query: Query<&mut Destination>,
+    mut path_found: EventWriter<PathFound>,
+    mut path_failed: EventWriter<PathFailed>,
 ) {
     for (entity_id, mut pending_path) in transform_tasks.iter_mut() {
-        if let Some(completion) = future::block_on(future::poll_once(&mut pending_path.0)) {
+        // Searches shared between actors are handled by `handle_shared_searches`
+        let task = match pending_path.task.as_mut() {
+            Some(task) => task,
+            None => continue,
+        };
+        if let Some((completion, stats, planner)) = future::block_on(future::poll_once(task)) {
+            diagnostics.add_measurement(PATH_SEARCH_TIME, stats.duration.as_secs_f64() * 1000.0);
+            diagnostics.add_measurement(PATH_NODES_EXPANDED, stats.expanded as f64);
+
             let mut entity = commands.entity(entity_id);
             entity.remove::<PendingPath>();
 
-            if let Some(path) = completion {
-                entity.insert(path);
-            } else {
-                log::info!("Could not find path for entity {:#?}", entity_id);
+            // The destination changed after this search started, its result is outdated
+            if !requests.is_latest(entity_id, pending_path.request) {
+                continue;
+            }
+
+            match completion {
+                Ok(path) => {
+                    if let Some(start) = pending_path.start {
+                        let window = path.0.len().min(cooperative.window as usize + 1);
+                        let planned = &path.0[..window];
+                        // Searches that ran at the same time did not see each other's claims
+                        if reservations.conflicts(entity_id, planned, start) {
+                            if let Ok(mut destination) = destination_query.get_mut(entity_id) {
+                                destination.set_changed();
+                            }
+                            continue;
+                        }
+
+                        let renew = (window < path.0.len()).then(|| start + cooperative.window / 2);
+                        reservations.reserve(entity_id, planned, start, renew);
+                        entity.insert(Timetable(start));
+                    }
+                    match planner {
+                        Some(planner) => entity.insert(IncrementalPlanner(planner)),
+                        None => entity.remove::<IncrementalPlanner>(),
+                    };
+
+                    path_found.send(PathFound {
+                        entity: entity_id,
+                        destination: path.0.last().copied().unwrap_or_default(),
+                        cost: path_cost(&navigation.grid, &path.0),
+                        length: path.0.len(),
+                    });
+                    entity.insert(path);
+                    if let Ok(destination) = destination_query.get_mut(entity_id) {
+                        entity.insert(PlannedFor(destination.0));
+                    }
+                }
+                Err(reason) => {
+                    entity
+                        .remove::<FollowPath>()
+                        .remove::<PathLegs>()
+                        .remove::<PlannedFor>();
+                    reservations.release(entity_id);
+                    log::info!("Could not find path for entity {:?}: {}", entity_id, reason);
+                    path_failed.send(PathFailed {
+                        entity: entity_id,
+                        reason,
+                    });
+                }
             }
         }
     }
 }
 
-fn heuristic(from: &UVec2, to: &UVec2) -> u32 {
-    let dx = from.x.abs_diff(to.x);
-    let dy = from.y.abs_diff(to.y);
-
-    STRAIGHT_COST * max(dx, dy) + (DIAGONAL_COST - STRAIGHT_COST) * min(dx, dy)
+/// Stop an actor from following a path its latest request could not replace.
+fn drop_path(commands: &mut Commands, entity: Entity) {
+    commands
+        .entity(entity)
+        .remove::<FollowPath>()
+        .remove::<PathLegs>()
+        .remove::<PlannedFor>();
 }
 
-fn add_entity_tie_breaker<H, I>(
-    seed: u64,
-    hasher_builder: H,
-    moves: I,
-) -> impl Iterator<Item = (UVec2, u32)>
-where
-    H: BuildHasher,
-    I: IntoIterator<Item = (UVec2, u32)>,
-{
-    moves.into_iter().map(move |(position, weight)| {
-        let mut hasher = hasher_builder.build_hasher();
-        hasher.write_u32(position.x);
-        hasher.write_u32(position.y);
-        hasher.write_u64(seed);
-        let hash: f64 = (hasher.finish() as u32).into();
-        // TODO implement own remap
-        let tie_breaker = remap(hash, 0.0f64..=f64::MAX, 0.0f64..=10.0f64) as u32;
-
-        (position, weight + tie_breaker)
-    })
-}
+/// Path to the tile closest to `to` of all tiles reachable from `from`.
+/// Of equally close tiles, the one that is cheapest to reach is picked.
+fn path_to_closest_reachable(
+    grid: &NavGrid,
+    from: &GridPosition,
+    to: &GridPosition,
+    corner_cutting: CornerCutting,
+) -> Option<Vec<GridPosition>> {
+    let parents = dijkstra_all(from, |node| grid.neighbours(node, corner_cutting));
+    let (_, _, closest) = parents
+        .iter()
+        .map(|(node, (_, cost))| (heuristic(node, to), *cost, *node))
+        .chain(iter::once((heuristic(from, to), 0, *from)))
+        .min_by_key(|(distance, cost, node)| {
+            (*distance, *cost, node.floor, node.tile.y, node.tile.x)
+        })?;
 
-fn neighbours(
-    storage: &TileStorage,
-    walkable: &[Entity],
-    mesh: Arc<Mutex<NavMesh>>,
-    current: UVec2,
-) -> Vec<(UVec2, u32)> {
-    // TODO cleanup, extract methods and after new neighbours calculation, simply call the nav_mesh again instead of remembering them during calculation
-    let mut nav_mesh = mesh.lock().unwrap();
-    match nav_mesh.get_vec(&current) {
-        None => {
-            let tile_pos = TilePos::from(current);
-            let mut neighbours = Vec::with_capacity(8usize);
-
-            match storage.get(&tile_pos) {
-                Some(current_entity) => {
-                    if walkable.contains(&current_entity) {
-                        let [north, south, west, east, northwest, northeast, southwest, southeast] =
-                            storage.get_neighboring_pos(&tile_pos);
-                        for neighbour in [north, south, west, east].into_iter().flatten() {
-                            if let Some(neighbour_entity) = storage.get(&neighbour) {
-                                if walkable.contains(&neighbour_entity) {
-                                    nav_mesh.insert(
-                                        current,
-                                        Move {
-                                            destination: neighbour.into(),
-                                            cost: STRAIGHT_COST,
-                                        },
-                                    );
-                                    neighbours.push(Move {
-                                        destination: neighbour.into(),
-                                        cost: STRAIGHT_COST,
-                                    });
-                                }
-                            }
-                        }
-                        for neighbour in [northwest, northeast, southwest, southeast]
-                            .into_iter()
-                            .flatten()
-                        {
-                            if let Some(neighbour_entity) = storage.get(&neighbour) {
-                                if walkable.contains(&neighbour_entity) {
-                                    nav_mesh.insert(
-                                        current,
-                                        Move {
-                                            destination: neighbour.into(),
-                                            cost: DIAGONAL_COST,
-                                        },
-                                    );
-                                    neighbours.push(Move {
-                                        destination: neighbour.into(),
-                                        cost: DIAGONAL_COST,
-                                    });
-                                }
-                            }
-                        }
-                    }
-                }
-                None => todo!(), // There is no entity here, so it is definitally not walkable
-            }
+    Some(build_path(&closest, &parents))
+}
 
-            // TODO cleanup
-            return neighbours
-                .iter()
-                .map(|neighbour: &Move| (neighbour.destination, neighbour.cost))
-                // TODO, do not collect here, but return the iterator itself
-                .collect();
-        }
-        Some(neighbours) => neighbours
-            .iter()
-            .map(|neighbour: &Move| (neighbour.destination, neighbour.cost))
-            // TODO, do not collect here, but return the iterator itself
-            .collect(),
-    }
+/// Cost of walking a path, without the tie-breakers added during the search.
+fn path_cost(grid: &NavGrid, path: &[GridPosition]) -> u32 {
+    path.windows(2)
+        .map(|step| {
+            let (from, to) = (step[0], step[1]);
+            let cost = if from.floor != to.floor {
+                STAIRS_COST
+            } else if from.tile.x != to.tile.x && from.tile.y != to.tile.y {
+                DIAGONAL_COST
+            } else {
+                STRAIGHT_COST
+            };
+            cost * grid.weight(&to) / 100
+        })
+        .sum()
 }
@@ -0,0 +1,546 @@
+use std::cell::Cell;
+use std::cmp::{max, min, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BinaryHeap;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet, Instant};
+use bevy_inspector_egui::egui::remap;
+use pathfinding::prelude::*;
+
+use crate::components::path_finding::grid::GridPosition;
+use crate::components::path_finding::path::PathAlgorithm;
+use crate::events::path_finding::PathFailure;
+use crate::map::raycast::line_tiles;
+use crate::resources::nav_grid::{CornerCutting, NavGrid, Navigation};
+use crate::resources::reservation::Slots;
+
+use super::d_star_lite::DStarLiteSearch;
+use super::jump_point::{fill_jumps, jump_point_successors, JumpPoint};
+use super::queue::HierarchicalSearch;
+use super::{DIAGONAL_COST, STAIRS_COST, STRAIGHT_COST};
+
+/// Everything a search needs besides the grid and its ends.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchParameters {
+    /// `Dna` of the actor, spreads actors over paths of equal cost.
+    pub seed: u64,
+    pub corner_cutting: CornerCutting,
+    pub hierarchical: HierarchicalSearch,
+    /// See `SchedulerSettings::search_timeout`.
+    pub timeout: Duration,
+}
+
+/// What a search did, to compare algorithms.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SearchStats {
+    /// Number of nodes whose successors were looked at.
+    pub expanded: usize,
+    pub duration: Duration,
+}
+
+/// An algorithm that finds paths on a snapshot of the navigation grid.
+/// Searches run on the async compute task pool, so finders are shared between threads.
+pub trait PathFinder: Send + Sync {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats);
+}
+
+impl PathAlgorithm {
+    pub fn finder(self) -> &'static dyn PathFinder {
+        match self {
+            PathAlgorithm::AStar => &AStar,
+            PathAlgorithm::Dijkstra => &Dijkstra,
+            PathAlgorithm::BreadthFirst => &BreadthFirst,
+            PathAlgorithm::ThetaStar => &ThetaStar,
+            PathAlgorithm::DStarLite => &DStarLite,
+        }
+    }
+}
+
+/// A* on the whole grid. Grids where every tile weighs the same are searched with jump points,
+/// long paths on the abstract graph.
+pub struct AStar;
+
+impl PathFinder for AStar {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let grid = &navigation.grid;
+        let budget = Budget::new(parameters.timeout);
+        let successors = |node: &GridPosition| budget.successors(grid, node, parameters);
+
+        // No tile is cheaper than the cheapest weight, so the scaled distance never overestimates
+        let cheapest = grid.cheapest_weight();
+        let estimate =
+            |from: &GridPosition, to: &GridPosition| heuristic(from, to) * cheapest / 100;
+
+        // Long searches run on the abstract graph and are refined one cluster at a time
+        let hierarchical = parameters.hierarchical;
+        let long = heuristic(from, to) >= hierarchical.min_distance * STRAIGHT_COST;
+        let hierarchical_path = (hierarchical.enabled && long)
+            .then(|| {
+                navigation.graph.find_path(
+                    grid,
+                    from,
+                    to,
+                    parameters.corner_cutting,
+                    &successors,
+                    estimate,
+                )
+            })
+            .flatten();
+
+        // Crossings the abstract graph has no transition for, like a diagonal squeezing past
+        // a corner, are only found by searching the whole grid
+        let path = hierarchical_path.or_else(|| {
+            // Jump points assume uniform move costs and diagonals that never cut corners
+            if grid.is_uniform() && parameters.corner_cutting == CornerCutting::Never {
+                let hasher_builder = BuildHasherDefault::<DefaultHasher>::default();
+                astar(
+                    &JumpPoint::start(*from),
+                    |point| {
+                        if !budget.expand() {
+                            return Vec::new();
+                        }
+
+                        jump_point_successors(grid, point, to)
+                            .into_iter()
+                            .map(|(next, cost)| {
+                                let extra =
+                                    tie_breaker(parameters.seed, &hasher_builder, &next.position);
+                                (next, cost + extra)
+                            })
+                            .collect::<Vec<_>>()
+                    },
+                    |point| estimate(&point.position, to),
+                    |point| point.position == *to,
+                )
+                .map(|(points, _)| fill_jumps(&points))
+            } else {
+                astar(
+                    from,
+                    |node| successors(node),
+                    |node| estimate(node, to),
+                    |node| node == to,
+                )
+                .map(|(path, _)| path)
+            }
+        });
+
+        budget.finish(path)
+    }
+}
+
+/// Dijkstra's algorithm, A* without a heuristic.
+pub struct Dijkstra;
+
+impl PathFinder for Dijkstra {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let budget = Budget::new(parameters.timeout);
+        let path = dijkstra(
+            from,
+            |node| budget.successors(&navigation.grid, node, parameters),
+            |node| node == to,
+        );
+
+        budget.finish(path.map(|(path, _)| path))
+    }
+}
+
+/// Breadth-first search, finds the path with the fewest moves whatever they cost.
+pub struct BreadthFirst;
+
+impl PathFinder for BreadthFirst {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let budget = Budget::new(parameters.timeout);
+        let path = bfs(
+            from,
+            |node| {
+                budget
+                    .successors(&navigation.grid, node, parameters)
+                    .into_iter()
+                    .map(|(next, _)| next)
+                    .collect::<Vec<_>>()
+            },
+            |node| node == to,
+        );
+
+        budget.finish(path)
+    }
+}
+
+/// Theta*, an any-angle A*. A tile is reached straight from the parent of the tile it was found
+/// from whenever the two can see each other, so paths are not bound to the eight directions of
+/// the grid. The line between two waypoints costs its length times the heaviest tile it crosses.
+/// The waypoints are filled in with the tiles the lines between them cross.
+pub struct ThetaStar;
+
+impl PathFinder for ThetaStar {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let grid = &navigation.grid;
+        let budget = Budget::new(parameters.timeout);
+        let cheapest = grid.cheapest_weight();
+        // Any-angle paths can be shorter than eight-directional ones, the estimate has to be too
+        let estimate = |node: &GridPosition| {
+            let straight = node.tile.as_vec2().distance(to.tile.as_vec2()) * STRAIGHT_COST as f32;
+            let climb = STAIRS_COST * node.floor.abs_diff(to.floor);
+            (straight as u32 + climb) * cheapest / 100
+        };
+        let line_cost = |from: &GridPosition, to: &GridPosition| {
+            let heaviest = line_tiles(from.tile, to.tile)
+                .into_iter()
+                .skip(1)
+                .map(|tile| grid.weight(&GridPosition::new(tile, from.floor)))
+                .max()
+                .unwrap_or(100);
+            let length = from.tile.as_vec2().distance(to.tile.as_vec2()) * STRAIGHT_COST as f32;
+            (length * heaviest as f32 / 100.0).ceil() as u32
+        };
+
+        // Parent and cost of every tile that was reached
+        let mut reached = HashMap::default();
+        reached.insert(*from, (*from, 0));
+        let mut closed = HashSet::default();
+        let mut open = BinaryHeap::new();
+        open.push(Reverse((
+            estimate(from),
+            0,
+            from.floor,
+            from.tile.y,
+            from.tile.x,
+        )));
+
+        let mut found = false;
+        while let Some(Reverse((_, cost, floor, y, x))) = open.pop() {
+            let node = GridPosition::new(UVec2::new(x, y), floor);
+            // Skip nodes that were queued again at a lower cost
+            if reached.get(&node).map_or(true, |(_, best)| *best < cost) || !closed.insert(node) {
+                continue;
+            }
+            if node == *to {
+                found = true;
+                break;
+            }
+
+            let parent = reached[&node].0;
+            let parent_cost = reached[&parent].1;
+            for (next, move_cost) in budget.successors(grid, &node, parameters) {
+                if closed.contains(&next) {
+                    continue;
+                }
+
+                let (next_parent, next_cost) =
+                    if parent != node && grid.line_of_sight(&parent, &next) {
+                        (parent, parent_cost + line_cost(&parent, &next))
+                    } else {
+                        (node, cost + move_cost)
+                    };
+
+                let improved = reached
+                    .get(&next)
+                    .map_or(true, |(_, best)| next_cost < *best);
+                if improved {
+                    reached.insert(next, (next_parent, next_cost));
+                    open.push(Reverse((
+                        next_cost + estimate(&next),
+                        next_cost,
+                        next.floor,
+                        next.tile.y,
+                        next.tile.x,
+                    )));
+                }
+            }
+        }
+
+        let path = found.then(|| {
+            let mut waypoints = vec![*to];
+            while let Some(last) = waypoints.last().copied() {
+                let parent = reached[&last].0;
+                if parent == last {
+                    break;
+                }
+                waypoints.push(parent);
+            }
+            waypoints.reverse();
+
+            let mut path = vec![*from];
+            for segment in waypoints.windows(2) {
+                let (start, end) = (segment[0], segment[1]);
+                if start.floor != end.floor {
+                    path.push(end);
+                    continue;
+                }
+                let tiles = line_tiles(start.tile, end.tile);
+                path.extend(
+                    tiles
+                        .into_iter()
+                        .skip(1)
+                        .map(|tile| GridPosition::new(tile, start.floor)),
+                );
+            }
+            path
+        });
+
+        budget.finish(path)
+    }
+}
+
+/// D* Lite, searches from the destination back to the actor. The search can be kept to repair
+/// the path when tiles change.
+pub struct DStarLite;
+
+impl DStarLite {
+    /// Find a path and keep the search that found it.
+    pub fn plan(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (
+        Result<Vec<GridPosition>, PathFailure>,
+        SearchStats,
+        DStarLiteSearch,
+    ) {
+        let grid = &navigation.grid;
+        let budget = Budget::new(parameters.timeout);
+        let mut search = DStarLiteSearch::new(grid, *from, *to, *parameters);
+        let finished = search.compute(grid, || budget.expand());
+        let path = finished.then(|| search.path(grid)).flatten();
+
+        let (path, stats) = budget.finish(path);
+        (path, stats, search)
+    }
+}
+
+impl PathFinder for DStarLite {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let (path, stats, _) = self.plan(navigation, from, to, parameters);
+        (path, stats)
+    }
+}
+
+/// Windowed cooperative A*: plans the first `window` steps in space and time around the tiles
+/// other actors claimed, waiting on a tile where it has to, and the rest of the way with `finder`.
+/// The path is walked on the clock described at `Timetable`.
+pub struct Cooperative {
+    pub finder: &'static dyn PathFinder,
+    pub slots: Arc<Slots>,
+    pub entity: Entity,
+    /// Step of the reservation clock the path starts at.
+    pub start: u32,
+    pub window: u32,
+}
+
+impl PathFinder for Cooperative {
+    fn find_path(
+        &self,
+        navigation: &Navigation,
+        from: &GridPosition,
+        to: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let grid = &navigation.grid;
+        let budget = Budget::new(parameters.timeout);
+        let cheapest = grid.cheapest_weight();
+        let free = |node: &GridPosition, next: &GridPosition, step: u32| {
+            let at = self.start + step;
+            self.slots.is_free(next, at + 1, self.entity)
+                && !self.slots.swaps(node, next, at, self.entity)
+        };
+
+        let timed = astar(
+            &(*from, 0),
+            |(node, step)| {
+                if *step >= self.window {
+                    return Vec::new();
+                }
+
+                let wait = (*node, STRAIGHT_COST);
+                let mut moves = budget.successors(grid, node, parameters);
+                moves.push(wait);
+                moves
+                    .into_iter()
+                    .filter(|(next, _)| free(node, next, *step))
+                    .map(|(next, cost)| ((next, step + 1), cost))
+                    .collect::<Vec<_>>()
+            },
+            |(node, _)| heuristic(node, to) * cheapest / 100,
+            // Past the window other actors are not planned far enough ahead to avoid
+            |(node, step)| node == to || *step >= self.window,
+        )
+        .map(|(path, _)| path.into_iter().map(|(node, _)| node).collect::<Vec<_>>());
+
+        let (mut path, stats) = budget.finish(timed);
+        let end = match path.as_ref().ok().and_then(|path| path.last()) {
+            Some(end) if end != to => *end,
+            _ => return (path, stats),
+        };
+
+        let (rest, rest_stats) = self.finder.find_path(navigation, &end, to, parameters);
+        path = path.and_then(|mut path| {
+            path.extend(rest?.into_iter().skip(1));
+            Ok(path)
+        });
+
+        let stats = SearchStats {
+            expanded: stats.expanded + rest_stats.expanded,
+            duration: stats.duration + rest_stats.duration,
+        };
+        (path, stats)
+    }
+}
+
+/// Counts the nodes a search expands and ends it once it runs out of time.
+struct Budget {
+    started: Instant,
+    timeout: Duration,
+    expanded: Cell<usize>,
+    timed_out: Cell<bool>,
+}
+
+impl Budget {
+    fn new(timeout: Duration) -> Self {
+        Budget {
+            started: Instant::now(),
+            timeout,
+            expanded: Cell::new(0),
+            timed_out: Cell::new(false),
+        }
+    }
+
+    /// Count an expanded node, false once the search is out of time.
+    fn expand(&self) -> bool {
+        if self.started.elapsed() > self.timeout {
+            self.timed_out.set(true);
+            return false;
+        }
+
+        self.expanded.set(self.expanded.get() + 1);
+        true
+    }
+
+    /// The moves from `node` with the actor's tie-breaker.
+    /// Without successors the search ends as if the destination was unreachable.
+    fn successors(
+        &self,
+        grid: &NavGrid,
+        node: &GridPosition,
+        parameters: &SearchParameters,
+    ) -> Vec<(GridPosition, u32)> {
+        if !self.expand() {
+            return Vec::new();
+        }
+
+        tie_broken_moves(grid, node, parameters)
+    }
+
+    fn finish(
+        self,
+        path: Option<Vec<GridPosition>>,
+    ) -> (Result<Vec<GridPosition>, PathFailure>, SearchStats) {
+        let result = match path {
+            Some(path) => Ok(path),
+            None if self.timed_out.get() => Err(PathFailure::TimedOut),
+            None => Err(PathFailure::Unreachable),
+        };
+        let stats = SearchStats {
+            expanded: self.expanded.get(),
+            duration: self.started.elapsed(),
+        };
+
+        (result, stats)
+    }
+}
+
+/// Cost of the cheapest path on tiles of 100% weight that ignores walls.
+pub fn heuristic(from: &GridPosition, to: &GridPosition) -> u32 {
+    let dx = from.tile.x.abs_diff(to.tile.x);
+    let dy = from.tile.y.abs_diff(to.tile.y);
+    // Staircases connect the same tile on adjacent floors, so each floor costs exactly one climb
+    let dz = from.floor.abs_diff(to.floor);
+
+    STRAIGHT_COST * max(dx, dy) + (DIAGONAL_COST - STRAIGHT_COST) * min(dx, dy) + STAIRS_COST * dz
+}
+
+/// The moves from `node`, with the actor's tie-breaker added to their cost.
+pub fn tie_broken_moves(
+    grid: &NavGrid,
+    node: &GridPosition,
+    parameters: &SearchParameters,
+) -> Vec<(GridPosition, u32)> {
+    // TODO share BuildHasherDefault for each call to successors
+    add_entity_tie_breaker(
+        parameters.seed,
+        BuildHasherDefault::<DefaultHasher>::default(),
+        grid.neighbours(node, parameters.corner_cutting),
+    )
+    .collect()
+}
+
+fn add_entity_tie_breaker<H, I>(
+    seed: u64,
+    hasher_builder: H,
+    moves: I,
+) -> impl Iterator<Item = (GridPosition, u32)>
+where
+    H: BuildHasher,
+    I: IntoIterator<Item = (GridPosition, u32)>,
+{
+    moves.into_iter().map(move |(position, weight)| {
+        (
+            position,
+            weight + tie_breaker(seed, &hasher_builder, &position),
+        )
+    })
+}
+
+/// A small extra cost for moving onto `position` that differs between actors, so actors with a
+/// different `Dna` spread over paths of equal cost.
+fn tie_breaker<H: BuildHasher>(seed: u64, hasher_builder: &H, position: &GridPosition) -> u32 {
+    let mut hasher = hasher_builder.build_hasher();
+    hasher.write_u32(position.tile.x);
+    hasher.write_u32(position.tile.y);
+    hasher.write_u32(position.floor);
+    hasher.write_u64(seed);
+    let hash: f64 = (hasher.finish() as u32).into();
+    // TODO implement own remap
+    remap(hash, 0.0f64..=f64::MAX, 0.0f64..=10.0f64) as u32
+}
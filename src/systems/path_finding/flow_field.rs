@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::utils::HashSet;
+use futures_lite::future;
+
+use crate::components::path_finding::grid::{Floor, GridPosition};
+use crate::components::steering::behaviour::FollowFlowField;
+use crate::events::path_finding::{PathFailed, PathFailure, PathFound};
+use crate::map::{world2d_to_grid, TileChanged};
+use crate::resources::flow_field::{FlowField, FlowFields};
+use crate::resources::nav_grid::{CornerCutting, Navigation};
+
+/// Fields are computed on the grid as it was, so any tile change makes all of them outdated.
+pub fn invalidate_flow_fields(
+    mut tile_changes: EventReader<TileChanged>,
+    mut flow_fields: ResMut<FlowFields>,
+) {
+    if tile_changes.iter().count() > 0 {
+        flow_fields.clear();
+    }
+}
+
+/// Start computing the fields that actors follow but that are missing, and forget the fields no
+/// actor follows any more.
+pub fn request_flow_fields(
+    mut flow_fields: ResMut<FlowFields>,
+    navigation: Res<Navigation>,
+    corner_cutting: Res<CornerCutting>,
+    follower_query: Query<&FollowFlowField>,
+) {
+    let destinations = follower_query
+        .iter()
+        .map(|follow_flow_field| follow_flow_field.destination)
+        .collect::<HashSet<_>>();
+    flow_fields.retain(&destinations);
+
+    let pool = AsyncComputeTaskPool::get();
+    for destination in destinations {
+        if flow_fields.contains(&destination) {
+            continue;
+        }
+
+        let grid = navigation.grid.clone();
+        let corner_cutting = *corner_cutting;
+        let task = pool.spawn(async move { FlowField::new(&grid, destination, corner_cutting) });
+        flow_fields.start(destination, task);
+    }
+}
+
+/// Hand computed fields to the actors following them.
+pub fn handle_completed_flow_fields(
+    mut commands: Commands,
+    mut flow_fields: ResMut<FlowFields>,
+    follower_query: Query<(Entity, &Transform, &Floor, &FollowFlowField)>,
+    mut path_found: EventWriter<PathFound>,
+    mut path_failed: EventWriter<PathFailed>,
+) {
+    let completed = flow_fields
+        .pending_mut()
+        .filter_map(|(_, task)| future::block_on(future::poll_once(task)))
+        .collect::<Vec<_>>();
+
+    for field in completed {
+        let followers = follower_query
+            .iter()
+            .filter(|(.., follow_flow_field)| follow_flow_field.destination == field.destination());
+
+        for (entity, transform, floor, _) in followers {
+            let path = world2d_to_grid(&transform.translation.truncate())
+                .and_then(|tile| field.path(&GridPosition::new(tile, floor.0)));
+
+            match path {
+                Some(path) => path_found.send(PathFound {
+                    entity,
+                    destination: field.destination(),
+                    cost: field.cost(&path[0]).unwrap_or_default(),
+                    length: path.len(),
+                }),
+                None => {
+                    commands.entity(entity).remove::<FollowFlowField>();
+                    path_failed.send(PathFailed {
+                        entity,
+                        reason: PathFailure::Unreachable,
+                    });
+                }
+            }
+        }
+
+        flow_fields.complete(field);
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ecs_tilemap::map::TilemapId;
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+
+use crate::components::path_finding::grid::{
+    Floor, GridPosition, Staircase, TerrainCost, Walkable,
+};
+use crate::map::{MapSize, TileChanged};
+use crate::resources::nav_grid::{CornerCutting, NavGrid, Navigation};
+
+/// Report tiles that became walkable or stopped being walkable, however their `Walkable` was changed.
+/// Removed components are only visible after the commands of the stage that removed them are applied,
+/// so this runs in `CoreStage::PostUpdate`.
+pub fn detect_walkable_changes(
+    added_query: Query<(&TilePos, &TilemapId), Added<Walkable>>,
+    removed: RemovedComponents<Walkable>,
+    tile_query: Query<(&TilePos, &TilemapId)>,
+    tilemap_query: Query<&Floor>,
+    mut tile_changes: EventWriter<TileChanged>,
+) {
+    // Despawned tiles have no position left, whoever despawns them reports the change
+    let removed_tiles = removed
+        .iter()
+        .filter_map(|entity| tile_query.get(entity).ok());
+
+    for (tile_pos, tilemap_id) in added_query.iter().chain(removed_tiles) {
+        if let Ok(floor) = tilemap_query.get(tilemap_id.0) {
+            let tile = UVec2::new(tile_pos.x, tile_pos.y);
+            tile_changes.send(TileChanged(GridPosition::new(tile, floor.0)));
+        }
+    }
+}
+
+/// Apply changed tiles to the navigation grid, its regions and the clusters of the abstract graph.
+/// A resized map starts from an empty grid and graph.
+/// Every tile of a new map is reported as changed once its `Walkable` is added.
+pub fn update_nav_grid(
+    mut tile_changes: EventReader<TileChanged>,
+    map_size: Res<MapSize>,
+    mut navigation: ResMut<Navigation>,
+    corner_cutting: Res<CornerCutting>,
+    tilemap_query: Query<(&Floor, &TileStorage)>,
+    tile_query: Query<(Option<&Walkable>, Option<&TerrainCost>, Option<&Staircase>)>,
+) {
+    let grid = &navigation.grid;
+    if grid.size() != map_size.tiles || grid.floors() != map_size.floors {
+        navigation.grid = Arc::new(NavGrid::new(map_size.tiles, map_size.floors));
+        navigation.graph = Arc::default();
+    }
+
+    if tile_changes.is_empty() {
+        return;
+    }
+
+    let storages = tilemap_query
+        .iter()
+        .map(|(floor, storage)| (floor.0, storage))
+        .collect::<HashMap<_, _>>();
+    // Searches that are still running keep the grid and graph they started with
+    let Navigation { grid, graph } = &mut *navigation;
+    let grid = Arc::make_mut(grid);
+    let mut changed = Vec::new();
+
+    for TileChanged(position) in tile_changes.iter() {
+        if !map_size.contains(position) {
+            continue;
+        }
+        changed.push(*position);
+
+        let tile = storages
+            .get(&position.floor)
+            .and_then(|storage| storage.get(&TilePos::from(position.tile)))
+            .and_then(|entity| tile_query.get(entity).ok());
+        let (walkable, cost, staircase) = match tile {
+            Some((walkable, cost, staircase)) => (
+                walkable.is_some(),
+                cost.map_or(1.0, |cost| cost.0),
+                staircase.map(|staircase| staircase.to),
+            ),
+            None => (false, 1.0, None),
+        };
+
+        grid.set_walkable(position, walkable);
+        grid.set_weight(position, cost);
+        grid.set_staircase(*position, staircase);
+    }
+
+    grid.update_regions(&changed, *corner_cutting);
+    Arc::make_mut(graph).update(grid, &changed, *corner_cutting);
+}
@@ -1,5 +1,13 @@
+pub mod chase;
+pub mod d_star_lite;
 pub mod find;
+pub mod finder;
+pub mod flow_field;
+pub mod jump_point;
+pub mod mesh;
+pub mod queue;
 pub mod steering;
 
 pub const STRAIGHT_COST: u32 = 100;
 pub const DIAGONAL_COST: u32 = 140;
+pub const STAIRS_COST: u32 = 300;
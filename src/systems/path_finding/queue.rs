@@ -0,0 +1,386 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, Instant};
+
+use crate::components::path_finding::grid::GridPosition;
+use crate::components::path_finding::path::{PathAlgorithm, PathPriority};
+use crate::resources::abstract_graph::CLUSTER_SIZE;
+use crate::TILE_SIZE;
+
+/// Number of path requests that are waiting for a search.
+pub const PATH_QUEUE_DEPTH: DiagnosticId =
+    DiagnosticId::from_u128(0x5b2e_91c4_7d3a_4f6e_b8a1_0c9d_2e4f_6a71);
+/// Time between requesting a path and starting its search.
+pub const PATH_WAIT_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0x3f8d_27a6_c1e4_4b9a_a5d2_6e0b_8c3f_1d94);
+/// Time a search ran for.
+pub const PATH_SEARCH_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0x9c41_d7e2_5a0f_4c83_b6e9_27d1_f08a_3b56);
+/// Requests answered by the path cache, or by a search for an identical request, each frame.
+pub const PATH_CACHE_HITS: DiagnosticId =
+    DiagnosticId::from_u128(0xd2a6_4e18_93bf_4a07_9c5e_71f3_0b8d_a4c2);
+/// Requests that started a search of their own while the path cache was used, each frame.
+pub const PATH_CACHE_MISSES: DiagnosticId =
+    DiagnosticId::from_u128(0x6b0f_c835_1d7e_4f92_a3c8_e54a_9f21_07db);
+/// Nodes a search expanded.
+pub const PATH_NODES_EXPANDED: DiagnosticId =
+    DiagnosticId::from_u128(0x47ea_0b93_e2c6_4d15_8f7a_c39b_51d2_e864);
+
+pub struct PathFindingRequest {
+    pub from: GridPosition,
+    pub to: GridPosition,
+    pub seed: u64,
+    pub priority: PathPriority,
+    /// The algorithm the actor asked for, the globally configured one when `None`.
+    pub algorithm: Option<PathAlgorithm>,
+}
+
+/// How many searches are started each frame.
+#[derive(Clone, Copy, Debug)]
+pub struct SchedulerSettings {
+    /// Time spent starting searches each frame, requests that do not fit wait for the next frame.
+    pub frame_budget: Duration,
+    /// Searches that may run at the same time.
+    pub max_in_flight: usize,
+    /// Searches that take longer give up with `PathFailure::TimedOut`.
+    pub search_timeout: Duration,
+    /// Once this many actors share a destination, they follow a single flow field towards it
+    /// instead of searching a path each.
+    pub flow_field_actors: usize,
+}
+
+impl Default for SchedulerSettings {
+    fn default() -> Self {
+        SchedulerSettings {
+            frame_budget: Duration::from_millis(1),
+            max_in_flight: 32,
+            search_timeout: Duration::from_millis(200),
+            flow_field_actors: 8,
+        }
+    }
+}
+
+impl SchedulerSettings {
+    /// Whether another search can start this frame, with `in_flight` searches running and the
+    /// frame's searches started at `started`.
+    pub fn can_start(&self, in_flight: usize, started: Instant) -> bool {
+        in_flight < self.max_in_flight && started.elapsed() < self.frame_budget
+    }
+}
+
+/// What to do with requests that cannot be searched as they are.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestFallbacks {
+    /// How far around a blocked start to look for a walkable tile to start from instead.
+    /// With a radius of 0 a blocked start fails with `PathFailure::StartBlocked`.
+    pub snap_radius: u32,
+    /// Walk to the reachable tile closest to an unreachable destination instead of failing.
+    pub closest_reachable: bool,
+}
+
+impl Default for RequestFallbacks {
+    fn default() -> Self {
+        RequestFallbacks {
+            snap_radius: 2,
+            closest_reachable: false,
+        }
+    }
+}
+
+/// When a search runs on the abstract graph instead of the whole grid.
+/// Hierarchical searches are much faster on large maps, but only optimal within the bound
+/// documented on `AbstractGraph`.
+#[derive(Clone, Copy, Debug)]
+pub struct HierarchicalSearch {
+    pub enabled: bool,
+    /// Destinations closer than this many tiles are searched on the whole grid.
+    pub min_distance: u32,
+}
+
+impl Default for HierarchicalSearch {
+    fn default() -> Self {
+        HierarchicalSearch {
+            enabled: true,
+            min_distance: 2 * CLUSTER_SIZE,
+        }
+    }
+}
+
+/// When actors that chase another entity search a new path.
+#[derive(Clone, Copy, Debug)]
+pub struct ChaseSettings {
+    /// Tiles the target may move away from where the path ends before a new path is searched.
+    pub replan_distance: u32,
+    /// Time an actor waits between two searches for the same target.
+    pub replan_interval: Duration,
+    /// Searches requested each frame for all chasing actors, the others wait their turn.
+    pub max_replans: usize,
+    /// Closer than this, in pixels, actors pursue their target without a path.
+    pub pursuit_range: f32,
+}
+
+impl Default for ChaseSettings {
+    fn default() -> Self {
+        ChaseSettings {
+            replan_distance: 2,
+            replan_interval: Duration::from_millis(500),
+            max_replans: 16,
+            pursuit_range: 2.0 * TILE_SIZE,
+        }
+    }
+}
+
+/// How many found paths are kept for identical requests.
+#[derive(Clone, Copy, Debug)]
+pub struct PathCacheSettings {
+    /// Paths kept at most, with a capacity of 0 nothing is cached and no searches are shared.
+    pub capacity: usize,
+    /// Only actors with the same `Dna` share paths, so crowds keep spreading over paths of equal
+    /// cost. Without it, actors with the same request all walk the same path.
+    pub vary_by_dna: bool,
+}
+
+impl Default for PathCacheSettings {
+    fn default() -> Self {
+        PathCacheSettings {
+            capacity: 256,
+            vary_by_dna: true,
+        }
+    }
+}
+
+/// Whether actors plan around the tiles other actors claimed for their paths, which keeps them
+/// from walking into each other in corridors.
+/// Each path claims its tiles for the steps it passes them at, for `window` steps ahead. Actors
+/// replan halfway through their window.
+#[derive(Clone, Copy, Debug)]
+pub struct CooperativeSearch {
+    pub enabled: bool,
+    /// How many steps ahead paths avoid each other.
+    pub window: u32,
+    /// How long an actor takes to walk from one tile to the next.
+    pub step: Duration,
+}
+
+impl Default for CooperativeSearch {
+    fn default() -> Self {
+        CooperativeSearch {
+            enabled: false,
+            window: 16,
+            step: Duration::from_millis(250),
+        }
+    }
+}
+
+struct QueuedRequest {
+    request: PathFindingRequest,
+    sequence: u64,
+    queued_at: Instant,
+}
+
+/// Orders the queue by priority, and by age within the same priority.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct QueueEntry {
+    priority: PathPriority,
+    sequence: Reverse<u64>,
+    entity: Entity,
+}
+
+/// Path requests that are waiting for a search, at most one per actor.
+/// Each request gets an ID, only the result of the latest request of an actor is used.
+#[derive(Default)]
+pub struct PathFindingRequests {
+    requests: HashMap<Entity, QueuedRequest>,
+    queue: BinaryHeap<QueueEntry>,
+    latest: HashMap<Entity, u64>,
+    next_sequence: u64,
+}
+
+impl PathFindingRequests {
+    /// Queue a request, replacing the request the actor was still waiting on.
+    pub fn request(&mut self, entity: Entity, request: PathFindingRequest) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.latest.insert(entity, sequence);
+
+        self.queue.push(QueueEntry {
+            priority: request.priority,
+            sequence: Reverse(sequence),
+            entity,
+        });
+        self.requests.insert(
+            entity,
+            QueuedRequest {
+                request,
+                sequence,
+                queued_at: Instant::now(),
+            },
+        );
+    }
+
+    /// The most urgent request with its ID and how long it has waited.
+    pub fn pop(&mut self) -> Option<(Entity, u64, PathFindingRequest, Duration)> {
+        while let Some(entry) = self.queue.pop() {
+            // Replaced requests stay in the heap until they come up
+            let current = self
+                .requests
+                .get(&entry.entity)
+                .map_or(false, |queued| queued.sequence == entry.sequence.0);
+            if !current {
+                continue;
+            }
+
+            if let Some(queued) = self.requests.remove(&entry.entity) {
+                let waited = queued.queued_at.elapsed();
+                return Some((entry.entity, queued.sequence, queued.request, waited));
+            }
+        }
+
+        None
+    }
+
+    /// Whether `request` is the last request made for the actor.
+    pub fn is_latest(&self, entity: Entity, request: u64) -> bool {
+        self.latest.get(&entity) == Some(&request)
+    }
+
+    /// Whether the actor has a request that no search was started for yet.
+    pub fn is_queued(&self, entity: Entity) -> bool {
+        self.requests.contains_key(&entity)
+    }
+
+    /// Forget the requests of an actor, results of searches that are still running are ignored.
+    pub fn cancel(&mut self, entity: Entity) {
+        self.requests.remove(&entity);
+        self.latest.remove(&entity);
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+pub fn setup_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(PATH_QUEUE_DEPTH, "path_queue_depth", 20));
+    diagnostics.add(Diagnostic::new(PATH_WAIT_TIME, "path_wait_time", 20).with_suffix("ms"));
+    diagnostics.add(Diagnostic::new(PATH_SEARCH_TIME, "path_search_time", 20).with_suffix("ms"));
+    diagnostics.add(Diagnostic::new(
+        PATH_NODES_EXPANDED,
+        "path_nodes_expanded",
+        20,
+    ));
+    diagnostics.add(Diagnostic::new(PATH_CACHE_HITS, "path_cache_hits", 20));
+    diagnostics.add(Diagnostic::new(PATH_CACHE_MISSES, "path_cache_misses", 20));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(priority: PathPriority, seed: u64) -> PathFindingRequest {
+        PathFindingRequest {
+            from: GridPosition::default(),
+            to: GridPosition::default(),
+            seed,
+            priority,
+            algorithm: None,
+        }
+    }
+
+    /// Starts searches like `calculate_paths` does for one frame, returns the seeds of the
+    /// requests it started.
+    fn start_frame(requests: &mut PathFindingRequests, settings: &SchedulerSettings) -> Vec<u64> {
+        let started = Instant::now();
+        let mut seeds = Vec::new();
+        while settings.can_start(seeds.len(), started) {
+            match requests.pop() {
+                Some((_, _, request, _)) => seeds.push(request.seed),
+                None => break,
+            }
+        }
+        seeds
+    }
+
+    fn settings(max_in_flight: usize, frame_budget: Duration) -> SchedulerSettings {
+        SchedulerSettings {
+            frame_budget,
+            max_in_flight,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn most_urgent_first_then_oldest_first() {
+        let mut requests = PathFindingRequests::default();
+        requests.request(Entity::from_raw(0), request(PathPriority::Idle, 0));
+        requests.request(Entity::from_raw(1), request(PathPriority::Normal, 1));
+        requests.request(Entity::from_raw(2), request(PathPriority::Ordered, 2));
+        requests.request(Entity::from_raw(3), request(PathPriority::Normal, 3));
+
+        let seeds = start_frame(&mut requests, &settings(8, Duration::from_secs(1)));
+
+        assert_eq!(seeds, vec![2, 1, 3, 0]);
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn new_request_replaces_the_queued_one() {
+        let mut requests = PathFindingRequests::default();
+        let actor = Entity::from_raw(0);
+        requests.request(actor, request(PathPriority::Ordered, 0));
+        requests.request(Entity::from_raw(1), request(PathPriority::Normal, 1));
+        requests.request(actor, request(PathPriority::Idle, 2));
+
+        assert_eq!(requests.len(), 2);
+        let (entity, id, request, _) = requests.pop().unwrap();
+        assert_eq!((entity, request.seed), (Entity::from_raw(1), 1));
+        assert!(requests.is_latest(Entity::from_raw(1), id));
+
+        // The replaced request is not searched, and its ID is outdated
+        let (entity, id, request, _) = requests.pop().unwrap();
+        assert_eq!((entity, request.seed), (actor, 2));
+        assert!(requests.is_latest(actor, id));
+        assert!(!requests.is_latest(actor, 0));
+        assert!(requests.pop().is_none());
+    }
+
+    #[test]
+    fn requests_over_the_in_flight_cap_wait_for_the_next_frame() {
+        let mut requests = PathFindingRequests::default();
+        for seed in 0..5 {
+            requests.request(
+                Entity::from_raw(seed as u32),
+                request(PathPriority::Normal, seed),
+            );
+        }
+        let settings = settings(2, Duration::from_secs(1));
+
+        assert_eq!(start_frame(&mut requests, &settings), vec![0, 1]);
+        assert_eq!(requests.len(), 3);
+        assert_eq!(start_frame(&mut requests, &settings), vec![2, 3]);
+        assert_eq!(start_frame(&mut requests, &settings), vec![4]);
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn requests_over_the_frame_budget_wait_for_the_next_frame() {
+        let mut requests = PathFindingRequests::default();
+        requests.request(Entity::from_raw(0), request(PathPriority::Normal, 0));
+        requests.request(Entity::from_raw(1), request(PathPriority::Ordered, 1));
+
+        assert!(start_frame(&mut requests, &settings(8, Duration::ZERO)).is_empty());
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            start_frame(&mut requests, &settings(8, Duration::from_secs(1))),
+            vec![1, 0]
+        );
+    }
+}
@@ -1,21 +1,235 @@
 use bevy::prelude::*;
 
 use crate::{
-    components::{path_finding::path::FoundPath, steering::behaviour::FollowPath},
-    map::grid_to_world2d,
+    components::{
+        path_finding::{
+            grid::{Floor, GridPosition},
+            path::{Destination, FoundPath, PathLeg, PathLegs, PendingPath, PlannedFor, Timetable},
+        },
+        steering::behaviour::{FollowFlowField, FollowPath, Seek},
+    },
+    events::path_finding::DestinationReached,
+    map::{grid_to_world2d, raycast::line_of_sight},
+    resources::{
+        nav_grid::{NavGrid, Navigation},
+        reservation::ReservationTable,
+    },
+    TILE_SIZE,
 };
 
-pub fn transform_path(mut commands: Commands, query: Query<(Entity, &FoundPath)>) {
-    for (entity, found_path) in query.iter() {
-        let path = found_path
-            .0
-            .iter()
-            .map(grid_to_world2d)
-            .collect::<Vec<Vec2>>();
+use super::queue::PathFindingRequests;
+
+/// Whether found paths are straightened before actors follow them.
+#[derive(Clone, Copy, Debug)]
+pub struct PathSmoothing {
+    /// Drop the waypoints an actor can see past, so it walks straight across open rooms instead
+    /// of from tile to tile.
+    pub enabled: bool,
+}
 
+impl Default for PathSmoothing {
+    fn default() -> Self {
+        PathSmoothing { enabled: true }
+    }
+}
+
+pub fn transform_path(
+    mut commands: Commands,
+    navigation: Res<Navigation>,
+    smoothing: Res<PathSmoothing>,
+    query: Query<(Entity, &FoundPath, Option<&Timetable>)>,
+) {
+    for (entity, found_path, timetable) in query.iter() {
+        // Smoothing would walk past the tiles a timetable reserved
+        let smooth = smoothing.enabled && timetable.is_none();
+        let legs = split_into_legs(&found_path.0, smooth.then(|| &*navigation.grid), timetable);
         commands
             .entity(entity)
             .remove::<FoundPath>()
-            .insert(FollowPath::new(path, 0.0, 10.0));
+            .remove::<Timetable>()
+            .remove::<FollowPath>()
+            .remove::<FollowFlowField>()
+            .insert(PathLegs(legs.into()));
     }
 }
+
+/// Hand the next leg of the path to the actor once it reached the end of the current one and
+/// the leg may depart.
+pub fn climb_stairs(
+    mut commands: Commands,
+    reservations: Res<ReservationTable>,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &mut Floor,
+        &mut PathLegs,
+        Option<&FollowPath>,
+    )>,
+) {
+    for (entity, transform, mut floor, mut legs, follow_path) in query.iter_mut() {
+        if let Some(follow_path) = follow_path {
+            let current_position = transform.translation.truncate();
+            let end = follow_path.path.last().unwrap();
+            if current_position.distance(*end) > TILE_SIZE / 2.0 {
+                continue;
+            }
+        }
+
+        let early = legs
+            .0
+            .front()
+            .map_or(false, |leg| leg.depart > reservations.now());
+        if early {
+            continue;
+        }
+
+        let mut entity = commands.entity(entity);
+        let leg = match legs.0.pop_front() {
+            Some(leg) => leg,
+            None => {
+                entity.remove::<PathLegs>();
+                continue;
+            }
+        };
+
+        floor.0 = leg.floor;
+        if leg.path.len() < 2 {
+            // Nothing to walk on this floor, the next leg is picked up in the next frame
+            entity.remove::<FollowPath>();
+        } else {
+            entity.insert(FollowPath::new(leg.path, 0.0, 10.0));
+        }
+
+        if legs.0.is_empty() {
+            entity.remove::<PathLegs>();
+        }
+    }
+}
+
+/// Stop actors that stand on their destination tile, or at the end of their last leg when the
+/// path ends on the closest reachable tile instead. Only the end of a path planned for the
+/// current destination counts.
+/// Actors still waiting for a path to a new destination are left alone, the path they follow
+/// leads to the previous one.
+#[allow(clippy::type_complexity)]
+pub fn arrive_at_destination(
+    mut commands: Commands,
+    requests: Res<PathFindingRequests>,
+    query: Query<
+        (
+            Entity,
+            &Transform,
+            &Floor,
+            &Destination,
+            Option<&FollowPath>,
+            Option<&PlannedFor>,
+        ),
+        (Without<PathLegs>, Without<FoundPath>, Without<PendingPath>),
+    >,
+    mut destination_reached: EventWriter<DestinationReached>,
+) {
+    for (entity, transform, floor, destination, follow_path, planned_for) in query.iter() {
+        if requests.is_queued(entity) {
+            continue;
+        }
+
+        let position = transform.translation.truncate();
+        let on_destination = floor.0 == destination.0.floor
+            && position.distance(grid_to_world2d(&destination.0.tile)) <= TILE_SIZE / 2.0;
+        let at_path_end = follow_path
+            .filter(|_| planned_for == Some(&PlannedFor(destination.0)))
+            .and_then(|follow_path| follow_path.path.last())
+            .map_or(false, |end| position.distance(*end) <= TILE_SIZE / 2.0);
+        let arrived = on_destination || at_path_end;
+        if !arrived {
+            continue;
+        }
+
+        commands
+            .entity(entity)
+            .remove::<Destination>()
+            .remove::<FollowPath>()
+            .remove::<PlannedFor>()
+            .remove::<FollowFlowField>()
+            .remove::<Seek>();
+        destination_reached.send(DestinationReached {
+            entity,
+            destination: destination.0,
+        });
+    }
+}
+
+/// Split a path into a leg per floor, smoothing each leg on `grid` if one is given.
+/// Paths with a timetable also end a leg on each tile that is waited on, the next leg departs
+/// once the wait is over.
+fn split_into_legs(
+    path: &[GridPosition],
+    grid: Option<&NavGrid>,
+    timetable: Option<&Timetable>,
+) -> Vec<PathLeg> {
+    let mut legs = Vec::new();
+
+    let mut start = 0;
+    for end in 1..=path.len() {
+        let waits = end < path.len() && path[end] == path[end - 1];
+        if end < path.len() && path[end].floor == path[start].floor && !waits {
+            continue;
+        }
+        // Waiting longer only moves the departure of the next leg
+        if waits && end - start == 1 {
+            start = end;
+            continue;
+        }
+
+        let leg = &path[start..end];
+        let depart = timetable.map_or(0, |timetable| timetable.0 + start as u32);
+        start = end;
+        let waypoints = match grid {
+            Some(grid) => smooth(grid, leg),
+            None => leg.to_vec(),
+        };
+        legs.push(PathLeg {
+            floor: leg[0].floor,
+            path: waypoints
+                .iter()
+                .map(|position| grid_to_world2d(&position.tile))
+                .collect(),
+            depart,
+        });
+    }
+
+    legs
+}
+
+/// Pull the path on a single floor tight: a waypoint is only kept where the line from the
+/// previous waypoint to the tile after it is blocked. Lines may not cross tiles that weigh more
+/// than the tiles of the part of the path they replace, so smoothing never leads onto mud the
+/// search went around.
+fn smooth(grid: &NavGrid, leg: &[GridPosition]) -> Vec<GridPosition> {
+    let (first, last) = match (leg.first(), leg.last()) {
+        (Some(first), Some(last)) if leg.len() > 2 => (*first, *last),
+        _ => return leg.to_vec(),
+    };
+
+    let mut waypoints = vec![first];
+    let mut anchor = first;
+    let mut heaviest = grid.weight(&first);
+    for step in leg.windows(2) {
+        let (previous, next) = (step[0], step[1]);
+        heaviest = heaviest.max(grid.weight(&next));
+
+        let visible = line_of_sight(anchor.tile, next.tile, |tile| {
+            let position = GridPosition::new(tile, anchor.floor);
+            grid.is_walkable(&position) && grid.weight(&position) <= heaviest
+        });
+        // Diagonals that cut a corner are kept as they are
+        if !visible && previous != anchor {
+            waypoints.push(previous);
+            anchor = previous;
+            heaviest = grid.weight(&previous).max(grid.weight(&next));
+        }
+    }
+    waypoints.push(last);
+
+    waypoints
+}
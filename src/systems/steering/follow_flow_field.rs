@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+
+use crate::components::path_finding::grid::{Floor, GridPosition};
+use crate::components::steering::behaviour::{FollowFlowField, Seek};
+use crate::events::path_finding::{PathFailed, PathFailure};
+use crate::map::{grid_to_world2d, world2d_to_grid};
+use crate::resources::flow_field::FlowFields;
+
+/// Seek the next tile of the flow field from the tile the actor is on.
+/// Actors wait while their field is computed, and take staircases the field leads over.
+/// Actors on a tile the field does not lead away from cannot reach the destination.
+pub fn follow_flow_field(
+    mut commands: Commands,
+    flow_fields: Res<FlowFields>,
+    mut query: Query<(Entity, &Transform, &mut Floor, &FollowFlowField)>,
+    mut path_failed: EventWriter<PathFailed>,
+) {
+    for (entity, transform, mut floor, follow_flow_field) in query.iter_mut() {
+        let destination = follow_flow_field.destination;
+        let field = flow_fields.get(&destination);
+        let tile = world2d_to_grid(&transform.translation.truncate())
+            .map(|tile| GridPosition::new(tile, floor.0));
+
+        let target = match (field, tile) {
+            (Some(_), Some(tile)) if tile == destination => destination,
+            (Some(field), Some(tile)) => match field.next(&tile) {
+                Some(next) => next,
+                None => {
+                    commands
+                        .entity(entity)
+                        .remove::<FollowFlowField>()
+                        .remove::<Seek>();
+                    path_failed.send(PathFailed {
+                        entity,
+                        reason: PathFailure::Unreachable,
+                    });
+                    continue;
+                }
+            },
+            _ => {
+                commands.entity(entity).remove::<Seek>();
+                continue;
+            }
+        };
+
+        // Staircases connect the same tile on two floors
+        if target.floor != floor.0 {
+            floor.0 = target.floor;
+            continue;
+        }
+
+        commands.entity(entity).insert(Seek {
+            target: grid_to_world2d(&target.tile),
+        });
+    }
+}
@@ -3,26 +3,43 @@ use bevy::prelude::*;
 use bevy_prototype_debug_lines::DebugLines;
 use bevy_prototype_lyon::prelude::DrawMode;
 
+use crate::components::path_finding::grid::{Floor, GridPosition};
 use crate::components::steering::boid::{Acceleration, MaxSpeed, Velocity};
+use crate::map::world2d_to_grid;
+use crate::resources::nav_grid::Navigation;
 
 use super::debug::color;
 
+pub mod follow_flow_field;
 pub mod follow_mouse;
 pub mod follow_path;
+pub mod pursuit;
 pub mod seek;
 
+/// Actors on expensive terrain are slowed down by the weight of the tile they are on.
 pub fn apply(
+    navigation: Res<Navigation>,
     mut query: Query<(
         Entity,
         &mut Velocity,
         &mut Transform,
         &mut Acceleration,
         &MaxSpeed,
+        Option<&Floor>,
     )>,
 ) {
-    for (_entity, mut velocity, mut transform, mut acceleration, max_speed) in query.iter_mut() {
+    for (_entity, mut velocity, mut transform, mut acceleration, max_speed, floor) in
+        query.iter_mut()
+    {
+        let weight = floor
+            .zip(world2d_to_grid(&transform.translation.truncate()))
+            .map_or(100, |(floor, tile)| {
+                navigation.grid.weight(&GridPosition::new(tile, floor.0))
+            });
+        let max_speed = max_speed.0 * 100.0 / weight as f32;
+
         velocity.0 += acceleration.0;
-        velocity.0 = velocity.0.clamp_length_max(max_speed.0);
+        velocity.0 = velocity.0.clamp_length_max(max_speed);
 
         let z = transform.translation.z;
         let new_translation = transform.translation.truncate() + velocity.0;
@@ -0,0 +1,12 @@
+use bevy::prelude::*;
+
+use crate::components::steering::behaviour::{Pursuit, Seek};
+
+/// Seek the position the target of a pursuit is heading for.
+pub fn pursuit(mut commands: Commands, query: Query<(Entity, &Pursuit)>) {
+    for (entity, pursuit) in query.iter() {
+        let target =
+            pursuit.target_current_position + pursuit.target_current_velocity * pursuit.lookahead;
+        commands.entity(entity).insert(Seek { target });
+    }
+}